@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use sdl2::image::LoadTexture;
+use sdl2::render::{Texture, TextureCreator};
+use sdl2::video::WindowContext;
+
+/// Slot index and the generation of the slot, a handle kept after its texture was freed stays invalid
+/// when the slot is reused
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TextureHandle {
+    index: usize,
+    generation: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct AssetError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to load {}: {}", self.path.display(), self.message)
+    }
+}
+
+enum TextureSlot {
+    /// Registered, loaded on first use
+    Unloaded,
+    Loaded(Texture),
+    /// Error is reported once, the texture is not retried
+    Failed,
+}
+
+struct TextureEntry {
+    path: PathBuf,
+    slot: TextureSlot,
+    ref_count: u32,
+}
+
+/// Owns every texture loaded from disk, de-duplicated by canonical path
+pub struct AssetManager {
+    textures: Vec<Option<TextureEntry>>,
+    /// Generation of every slot in `textures`, increased when its texture is freed
+    generations: Vec<u32>,
+    by_path: HashMap<PathBuf, TextureHandle>,
+}
+
+impl AssetManager {
+    pub fn new() -> Self {
+        Self {
+            textures: vec![],
+            generations: vec![],
+            by_path: HashMap::new(),
+        }
+    }
+
+    fn entry_mut(&mut self, handle: TextureHandle) -> Option<&mut TextureEntry> {
+        if self.generations.get(handle.index) != Some(&handle.generation) {
+            return None;
+        }
+        self.textures[handle.index].as_mut()
+    }
+
+    /// Returns a handle to the texture and increases its reference count.
+    /// The texture itself is loaded the first time it is used.
+    pub fn acquire_texture(&mut self, path: impl AsRef<Path>) -> Result<TextureHandle, AssetError> {
+        let path = path.as_ref();
+        let canonical = std::fs::canonicalize(path).map_err(|e| AssetError {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })?;
+        if let Some(handle) = self.by_path.get(&canonical).copied() {
+            self.entry_mut(handle).unwrap().ref_count += 1;
+            return Ok(handle);
+        }
+        let entry = TextureEntry {
+            path: canonical.clone(),
+            slot: TextureSlot::Unloaded,
+            ref_count: 1,
+        };
+        let index = match self.textures.iter().position(|e| e.is_none()) {
+            Some(free) => {
+                self.textures[free] = Some(entry);
+                free
+            }
+            None => {
+                self.textures.push(Some(entry));
+                self.generations.push(0);
+                self.textures.len() - 1
+            }
+        };
+        let handle = TextureHandle { index, generation: self.generations[index] };
+        self.by_path.insert(canonical, handle);
+        Ok(handle)
+    }
+
+    /// Acquires `path` once and remembers the handle in `cache`, logs errors
+    pub fn acquire_cached(
+        &mut self,
+        cache: &mut HashMap<String, TextureHandle>,
+        path: &str,
+    ) -> Option<TextureHandle> {
+        if let Some(handle) = cache.get(path) {
+            return Some(*handle);
+        }
+        match self.acquire_texture(path) {
+            Ok(handle) => {
+                cache.insert(path.to_owned(), handle);
+                Some(handle)
+            }
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Handles of freed textures are ignored
+    pub fn release_texture(&mut self, handle: TextureHandle) {
+        if let Some(entry) = self.entry_mut(handle) {
+            entry.ref_count = entry.ref_count.saturating_sub(1);
+        }
+    }
+
+    /// Loads the texture if needed, returns None if it failed to load or the handle was freed
+    pub fn texture(
+        &mut self,
+        texture_creator: &TextureCreator<WindowContext>,
+        handle: TextureHandle,
+    ) -> Option<&mut Texture> {
+        let entry = self.entry_mut(handle)?;
+        if let TextureSlot::Unloaded = entry.slot {
+            entry.slot = match texture_creator.load_texture(&entry.path) {
+                Ok(texture) => TextureSlot::Loaded(texture),
+                Err(message) => {
+                    let error = AssetError {
+                        path: entry.path.clone(),
+                        message,
+                    };
+                    println!("{}", error);
+                    TextureSlot::Failed
+                }
+            };
+        }
+        match &mut entry.slot {
+            TextureSlot::Loaded(texture) => Some(texture),
+            _ => None,
+        }
+    }
+
+    /// Drops loaded textures so they are read from disk again on next use
+    pub fn reload_textures(&mut self) {
+        for entry in self.textures.iter_mut().flatten() {
+            let slot = std::mem::replace(&mut entry.slot, TextureSlot::Unloaded);
+            if let TextureSlot::Loaded(texture) = slot {
+                unsafe { texture.destroy() };
+            }
+        }
+    }
+
+    /// Frees textures nobody holds a handle to anymore, returns how many were freed
+    pub fn unload_unused(&mut self) -> usize {
+        let mut freed = 0;
+        for (slot, generation) in self.textures.iter_mut().zip(self.generations.iter_mut()) {
+            let unused = matches!(slot, Some(entry) if entry.ref_count == 0);
+            if unused {
+                let entry = slot.take().unwrap();
+                *generation += 1;
+                self.by_path.remove(&entry.path);
+                if let TextureSlot::Loaded(texture) = entry.slot {
+                    unsafe { texture.destroy() };
+                }
+                freed += 1;
+            }
+        }
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ref_count(assets: &mut AssetManager, handle: TextureHandle) -> Option<u32> {
+        assets.entry_mut(handle).map(|entry| entry.ref_count)
+    }
+
+    #[test]
+    fn textures_are_shared_and_counted() {
+        let mut assets = AssetManager::new();
+        let a = assets.acquire_texture("res/glyphs/button_a.png").unwrap();
+        let b = assets.acquire_texture("res/glyphs/button_b.png").unwrap();
+        // the same file through another path is the same texture
+        let a2 = assets.acquire_texture("res/glyphs/../glyphs/button_a.png").unwrap();
+        assert_eq!(a, a2);
+        assert_ne!(a, b);
+        assert_eq!(ref_count(&mut assets, a), Some(2));
+
+        assets.release_texture(a);
+        assert_eq!(assets.unload_unused(), 0);
+        assets.release_texture(a);
+        assets.release_texture(a);
+        assert_eq!(ref_count(&mut assets, a), Some(0));
+        assert_eq!(assets.unload_unused(), 1);
+        assert_eq!(ref_count(&mut assets, a), None);
+        assert_eq!(ref_count(&mut assets, b), Some(1));
+        assert!(assets.acquire_texture("res/missing.png").is_err());
+    }
+
+    #[test]
+    fn freed_handles_do_not_point_at_reused_slots() {
+        let mut assets = AssetManager::new();
+        let old = assets.acquire_texture("res/glyphs/button_a.png").unwrap();
+        assets.release_texture(old);
+        assets.unload_unused();
+
+        let new = assets.acquire_texture("res/glyphs/button_x.png").unwrap();
+        assert_eq!(new.index, old.index);
+        assert_ne!(new, old);
+        assert_eq!(ref_count(&mut assets, old), None);
+        // releasing the stale handle leaves the new texture alone
+        assets.release_texture(old);
+        assert_eq!(ref_count(&mut assets, new), Some(1));
+        assert_eq!(assets.unload_unused(), 0);
+
+        // loading the freed path again gets a fresh handle too
+        let again = assets.acquire_texture("res/glyphs/button_a.png").unwrap();
+        assert_ne!(again, old);
+        assert_eq!(ref_count(&mut assets, again), Some(1));
+    }
+}
@@ -0,0 +1,657 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use project_tomato::properties::{MUSIC, RADIUS, SOUND, VOLUME};
+use sdl2::audio::{AudioCVT, AudioCallback, AudioDevice, AudioFormat, AudioSpecDesired, AudioSpecWAV};
+use sdl2::Sdl;
+
+use crate::{
+    map_error::{float_property, string_property, MapLoadError},
+    render::TilemapState,
+    settings::AudioSettings,
+};
+
+pub const DIALOG_PAGE_SOUND: &str = "res/sfx/dialog_page.wav";
+pub const DIALOG_CLOSE_SOUND: &str = "res/sfx/dialog_close.wav";
+/// Seconds music tracks take to fade into each other on map changes
+pub const CROSSFADE_TIME: f32 = 1.0;
+/// Distance in pixels from the middle of the screen at which positional sounds fade out
+pub const HEARING_RADIUS: f32 = 400.0;
+/// Music volume while a dialog is open
+const DUCK_VOLUME: f32 = 0.35;
+/// How fast the music ducks and comes back, in volume per second
+const DUCK_SPEED: f32 = 2.0;
+
+const FREQ: i32 = 44100;
+const CHANNELS: u8 = 2;
+
+/// Gameplay sound effects
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sfx {
+    Jump,
+    Land,
+    Stomp,
+    Death,
+    Interact,
+    Bump,
+}
+
+impl Sfx {
+    pub fn path(&self) -> &'static str {
+        match self {
+            Sfx::Jump => "res/sfx/jump.wav",
+            Sfx::Land => "res/sfx/land.wav",
+            Sfx::Stomp => "res/sfx/stomp.wav",
+            Sfx::Death => "res/sfx/death.wav",
+            Sfx::Interact => "res/sfx/interact.wav",
+            Sfx::Bump => "res/sfx/bump.wav",
+        }
+    }
+}
+
+/// Mono samples at the mixer frequency
+type Samples = Arc<Vec<f32>>;
+
+/// A sound effect being played by the mixer
+struct Playing {
+    samples: Samples,
+    /// Fractional position in `samples`, advanced by `pitch` every output frame
+    position: f64,
+    pitch: f64,
+    volume: f32,
+    /// -1.0 is fully left, 1.0 fully right
+    pan: f32,
+}
+
+/// A looping sound placed in the map, its volume and pan follow the camera
+struct AmbientLoop {
+    samples: Samples,
+    position: usize,
+    volume: f32,
+    pan: f32,
+}
+
+/// A looping music track
+struct Track {
+    samples: Samples,
+    position: usize,
+    gain: f32,
+    /// Gain change per second, the track is dropped once it fades out
+    fade: f32,
+}
+
+/// Mixes every playing sound and music track, runs on the audio thread
+pub struct Mixer {
+    freq: i32,
+    channels: usize,
+    sounds: Vec<Playing>,
+    music: Vec<Track>,
+    ambient: Vec<AmbientLoop>,
+    volume: AudioSettings,
+    /// Music gain moving towards `duck_target`
+    duck: f32,
+    duck_target: f32,
+}
+
+impl Mixer {
+    fn new(freq: i32, channels: u8) -> Self {
+        Self {
+            freq,
+            channels: channels.max(1) as usize,
+            sounds: vec![],
+            music: vec![],
+            ambient: vec![],
+            volume: AudioSettings::default(),
+            duck: 1.0,
+            duck_target: 1.0,
+        }
+    }
+
+    /// Fills `out` with interleaved samples
+    pub fn mix(&mut self, out: &mut [f32]) {
+        out.iter_mut().for_each(|sample| *sample = 0.0);
+        let sfx_volume = self.volume.master * self.volume.sfx;
+        for sound in self.sounds.iter_mut() {
+            for frame in out.chunks_mut(self.channels) {
+                let index = sound.position as usize;
+                if index + 1 >= sound.samples.len() {
+                    sound.position = sound.samples.len() as f64;
+                    break;
+                }
+                // linear interpolation keeps pitched sounds from crackling
+                let t = (sound.position - index as f64) as f32;
+                let value = sound.samples[index] * (1.0 - t) + sound.samples[index + 1] * t;
+                add_panned(frame, value * sound.volume * sfx_volume, sound.pan);
+                sound.position += sound.pitch;
+            }
+        }
+        self.sounds
+            .retain(|sound| (sound.position as usize) + 1 < sound.samples.len());
+
+        for ambient in self.ambient.iter_mut() {
+            for frame in out.chunks_mut(self.channels) {
+                let value = ambient.samples[ambient.position];
+                add_panned(frame, value * ambient.volume * sfx_volume, ambient.pan);
+                ambient.position = (ambient.position + 1) % ambient.samples.len();
+            }
+        }
+
+        let music_volume = self.volume.master * self.volume.music;
+        let fade_step = 1.0 / self.freq as f32;
+        let duck_from = self.duck;
+        for track in self.music.iter_mut() {
+            let mut duck = duck_from;
+            for frame in out.chunks_mut(self.channels) {
+                duck = step_towards(duck, self.duck_target, DUCK_SPEED * fade_step);
+                let value = track.samples[track.position];
+                for sample in frame.iter_mut() {
+                    *sample += value * track.gain * duck * music_volume;
+                }
+                track.position = (track.position + 1) % track.samples.len();
+                track.gain = (track.gain + track.fade * fade_step).clamp(0.0, 1.0);
+            }
+        }
+        self.music.retain(|track| track.gain > 0.0 || track.fade >= 0.0);
+        let frames = out.len() / self.channels;
+        self.duck = step_towards(self.duck, self.duck_target, DUCK_SPEED * fade_step * frames as f32);
+        out.iter_mut().for_each(|sample| *sample = sample.clamp(-1.0, 1.0));
+    }
+}
+
+/// Adds `value` to both sides of a stereo frame, the side away from `pan` gets quieter
+fn add_panned(frame: &mut [f32], value: f32, pan: f32) {
+    match frame {
+        [left, right, ..] => {
+            *left += value * (1.0 - pan).min(1.0);
+            *right += value * (1.0 + pan).min(1.0);
+        }
+        _ => frame.iter_mut().for_each(|sample| *sample += value),
+    }
+}
+
+fn step_towards(value: f32, target: f32, step: f32) -> f32 {
+    if value < target {
+        (value + step).min(target)
+    } else {
+        (value - step).max(target)
+    }
+}
+
+impl AudioCallback for Mixer {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.mix(out);
+    }
+}
+
+enum AudioBackend {
+    Device(AudioDevice<Mixer>),
+    /// Nothing is heard, the mixer is advanced by `update_audio` so headless runs behave the same
+    Null(Mixer),
+}
+
+pub struct AudioState {
+    backend: AudioBackend,
+    freq: i32,
+    /// Loaded sounds by path, sounds that failed to load are empty
+    sounds: HashMap<String, Samples>,
+    /// Path of the music that is playing or fading in
+    music: Option<String>,
+    /// Looping sounds of the current map, in the same order as the mixer's
+    ambient: Vec<AmbientSound>,
+    listener: Listener,
+    rng: u32,
+}
+
+/// Where positional sounds are heard from, the middle of the screen in world coordinates
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Listener {
+    pub x: f32,
+    pub y: f32,
+    /// Sounds this far to the side are panned fully
+    pub half_width: f32,
+}
+
+/// Looping sound placed in the `Sounds` object layer of a map
+#[derive(Clone, PartialEq, Debug)]
+pub struct AmbientSound {
+    pub sound: String,
+    pub x: f32,
+    pub y: f32,
+    pub volume: f32,
+    /// Distance at which the sound can no longer be heard
+    pub radius: f32,
+}
+
+impl AudioState {
+    /// Opens the default audio device, falls back to the null backend without one
+    pub fn new(sdl: &Sdl) -> Self {
+        let desired = AudioSpecDesired {
+            freq: Some(FREQ),
+            channels: Some(CHANNELS),
+            samples: Some(512),
+        };
+        let device = sdl
+            .audio()
+            .and_then(|audio| audio.open_playback(None, &desired, |spec| Mixer::new(spec.freq, spec.channels)));
+        match device {
+            Ok(device) => {
+                device.resume();
+                let freq = device.spec().freq;
+                Self::with_backend(AudioBackend::Device(device), freq)
+            }
+            Err(e) => {
+                println!("Failed to open audio device, the game will be silent: {}", e);
+                Self::null()
+            }
+        }
+    }
+
+    pub fn null() -> Self {
+        Self::with_backend(AudioBackend::Null(Mixer::new(FREQ, CHANNELS)), FREQ)
+    }
+
+    fn with_backend(backend: AudioBackend, freq: i32) -> Self {
+        Self {
+            backend,
+            freq,
+            sounds: HashMap::new(),
+            music: None,
+            ambient: vec![],
+            listener: Listener { x: 0.0, y: 0.0, half_width: 1.0 },
+            rng: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0)
+                | 1,
+        }
+    }
+
+    /// Runs `f` with the mixer, locking the audio thread out while it runs
+    fn with_mixer<R>(&mut self, f: impl FnOnce(&mut Mixer) -> R) -> R {
+        match &mut self.backend {
+            AudioBackend::Device(device) => f(&mut device.lock()),
+            AudioBackend::Null(mixer) => f(mixer),
+        }
+    }
+
+    /// xorshift, returns a value in -1..1
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng % 20000) as f32 / 10000.0 - 1.0
+    }
+
+    /// `value` changed randomly by at most `variation` either way
+    pub fn vary(&mut self, value: f32, variation: f32) -> f32 {
+        value + variation * self.random()
+    }
+
+    fn sound(&mut self, path: &str) -> Samples {
+        if let Some(samples) = self.sounds.get(path) {
+            return samples.clone();
+        }
+        let samples = match load_wav(path, self.freq) {
+            Ok(samples) => samples,
+            Err(e) => {
+                println!("Failed to load sound {}: {}", path, e);
+                vec![]
+            }
+        };
+        let samples = Arc::new(samples);
+        self.sounds.insert(path.to_owned(), samples.clone());
+        samples
+    }
+
+    /// Forgets loaded sounds so they are read from disk again
+    pub fn reload_sounds(&mut self) {
+        self.sounds.clear();
+    }
+}
+
+/// Loads a wav file as mono f32 samples at `freq`
+fn load_wav(path: &str, freq: i32) -> Result<Vec<f32>, String> {
+    let wav = AudioSpecWAV::load_wav(path)?;
+    let cvt = AudioCVT::new(wav.format, wav.channels, wav.freq, AudioFormat::F32LSB, 1, freq)?;
+    let data = cvt.convert(wav.buffer().to_vec());
+    Ok(data
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect())
+}
+
+/// Reads the `music` map property
+pub fn load_tilemap_music(tile_state: &TilemapState) -> Result<Option<String>, MapLoadError> {
+    Ok(string_property(&tile_state.properties, MUSIC)?.cloned())
+}
+
+/// Reads the objects of the `Sounds` layer, they are placed at their center
+pub fn load_tilemap_ambient_sounds(tile_state: &TilemapState) -> Result<Vec<AmbientSound>, MapLoadError> {
+    let mut sounds = vec![];
+    for layer in tile_state.layers() {
+        if layer.name != "Sounds" {
+            continue;
+        }
+        if let tiled::LayerType::ObjectLayer(obj_layer) = layer.layer_type() {
+            for obj in obj_layer.objects() {
+                let at_object = |e: MapLoadError| e.in_object(&layer.name, obj.id());
+                let (x, y) = match obj.shape {
+                    tiled::ObjectShape::Rect { width, height }
+                    | tiled::ObjectShape::Ellipse { width, height } => {
+                        (obj.x + width / 2.0, obj.y + height / 2.0)
+                    }
+                    tiled::ObjectShape::Point(..) => (obj.x, obj.y),
+                    _ => continue,
+                };
+                let sound = match string_property(&obj.properties, SOUND).map_err(at_object)? {
+                    Some(sound) => sound.clone(),
+                    None => continue,
+                };
+                let volume = float_property(&obj.properties, VOLUME)
+                    .map_err(at_object)?
+                    .unwrap_or(1.0);
+                let radius = float_property(&obj.properties, RADIUS)
+                    .map_err(at_object)?
+                    .unwrap_or(HEARING_RADIUS);
+                sounds.push(AmbientSound { sound, x, y, volume, radius });
+            }
+        }
+    }
+    Ok(sounds)
+}
+
+/// Volume and pan of a sound at `x`, `y`. Full volume within a third of `radius`, silent beyond it.
+pub fn spatialize(listener: &Listener, x: f32, y: f32, radius: f32) -> (f32, f32) {
+    let dx = x - listener.x;
+    let dy = y - listener.y;
+    let distance = (dx * dx + dy * dy).sqrt();
+    let full = radius / 3.0;
+    let gain = (1.0 - (distance - full) / (radius - full).max(0.001)).clamp(0.0, 1.0);
+    let pan = (dx / listener.half_width.max(1.0)).clamp(-1.0, 1.0);
+    (gain, pan)
+}
+
+/// Moves the listener to the middle of the screen, `camera` is the inverted offset kept by the renderer
+pub fn set_listener(audio: &mut AudioState, camera: &sdl2::rect::Rect, view: (u32, u32)) {
+    audio.listener = Listener {
+        x: -camera.x() as f32 + view.0 as f32 / 2.0,
+        y: -camera.y() as f32 + view.1 as f32 / 2.0,
+        half_width: view.0 as f32 / 2.0,
+    };
+}
+
+/// Replaces the looping sounds of the previous map, sounds that failed to load are left out
+pub fn set_ambient_sounds(audio: &mut AudioState, sounds: Vec<AmbientSound>) {
+    let mut loops = vec![];
+    let mut ambient = vec![];
+    for sound in sounds {
+        let samples = audio.sound(&sound.sound);
+        // an empty loop would be indexed by the audio thread before the next update
+        if samples.is_empty() {
+            continue;
+        }
+        loops.push(AmbientLoop { samples, position: 0, volume: 0.0, pan: 0.0 });
+        ambient.push(sound);
+    }
+    audio.ambient = ambient;
+    audio.with_mixer(|mixer| mixer.ambient = loops);
+}
+
+/// Lowers the music while `ducked`, it fades back once the dialog closes
+pub fn set_music_ducked(audio: &mut AudioState, ducked: bool) {
+    let target = if ducked { DUCK_VOLUME } else { 1.0 };
+    audio.with_mixer(|mixer| mixer.duck_target = target);
+}
+
+/// Updates ambient sounds to the listener and advances the null backend, the audio device keeps its own time
+pub fn update_audio(audio: &mut AudioState, dt: f32) {
+    let listener = audio.listener;
+    let placed: Vec<(f32, f32)> = audio
+        .ambient
+        .iter()
+        .map(|ambient| {
+            let (gain, pan) = spatialize(&listener, ambient.x, ambient.y, ambient.radius);
+            (gain * ambient.volume, pan)
+        })
+        .collect();
+    audio.with_mixer(|mixer| {
+        for (ambient, (volume, pan)) in mixer.ambient.iter_mut().zip(placed) {
+            ambient.volume = volume;
+            ambient.pan = pan;
+        }
+    });
+    if let AudioBackend::Null(mixer) = &mut audio.backend {
+        let frames = (dt.clamp(0.0, 1.0) * mixer.freq as f32) as usize;
+        let mut out = vec![0.0; frames * mixer.channels];
+        mixer.mix(&mut out);
+    }
+}
+
+pub fn set_volume(audio: &mut AudioState, volume: &AudioSettings) {
+    let volume = volume.clone();
+    audio.with_mixer(|mixer| mixer.volume = volume);
+}
+
+/// Plays the sound at `path`, `pitch` 2.0 is an octave up and twice as fast
+pub fn play_sound(audio: &mut AudioState, path: &str, volume: f32, pitch: f32) {
+    play_panned(audio, path, volume, pitch, 0.0);
+}
+
+fn play_panned(audio: &mut AudioState, path: &str, volume: f32, pitch: f32, pan: f32) {
+    let samples = audio.sound(path);
+    if samples.len() < 2 {
+        return;
+    }
+    audio.with_mixer(|mixer| {
+        mixer.sounds.push(Playing {
+            samples,
+            position: 0.0,
+            pitch: pitch.max(0.01) as f64,
+            volume,
+            pan,
+        })
+    });
+}
+
+/// Plays a gameplay sound with a slightly random pitch so repeats do not sound the same
+pub fn play_sfx(audio: &mut AudioState, sfx: Sfx) {
+    let pitch = audio.vary(1.0, 0.05);
+    play_sound(audio, sfx.path(), 1.0, pitch);
+}
+
+/// Plays a sound from a world position, panned and quieter the further it is from the middle of the screen
+pub fn play_sound_at(audio: &mut AudioState, path: &str, volume: f32, pitch: f32, x: f32, y: f32) {
+    let (gain, pan) = spatialize(&audio.listener, x, y, HEARING_RADIUS);
+    if gain <= 0.0 {
+        return;
+    }
+    play_panned(audio, path, volume * gain, pitch, pan);
+}
+
+/// `play_sfx` from a world position
+pub fn play_sfx_at(audio: &mut AudioState, sfx: Sfx, x: f32, y: f32) {
+    let pitch = audio.vary(1.0, 0.05);
+    play_sound_at(audio, sfx.path(), 1.0, pitch, x, y);
+}
+
+/// Crossfades to the music at `path` over `fade` seconds, None fades the music out.
+/// The music that is already playing keeps playing.
+pub fn play_music(audio: &mut AudioState, path: Option<&str>, fade: f32) {
+    if audio.music.as_deref() == path {
+        return;
+    }
+    audio.music = path.map(|path| path.to_owned());
+    let samples = path.map(|path| audio.sound(path)).filter(|samples| !samples.is_empty());
+    let fade_speed = 1.0 / fade.max(0.001);
+    audio.with_mixer(|mixer| {
+        for track in mixer.music.iter_mut() {
+            track.fade = -fade_speed;
+        }
+        if let Some(samples) = samples {
+            mixer.music.push(Track { samples, position: 0, gain: 0.0, fade: fade_speed });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn null_with_sounds(sounds: &[(&str, usize)]) -> AudioState {
+        let mut audio = AudioState::null();
+        for (path, len) in sounds {
+            audio.sounds.insert(path.to_string(), Arc::new(vec![0.5; *len]));
+        }
+        audio
+    }
+
+    fn music_gains(audio: &mut AudioState) -> Vec<f32> {
+        audio.with_mixer(|mixer| mixer.music.iter().map(|track| track.gain).collect())
+    }
+
+    #[test]
+    fn sounds_end_on_the_null_backend() {
+        let mut audio = null_with_sounds(&[("beep", FREQ as usize / 10)]);
+        play_sound(&mut audio, "beep", 1.0, 1.0);
+        update_audio(&mut audio, 0.05);
+        assert_eq!(audio.with_mixer(|mixer| mixer.sounds.len()), 1);
+        update_audio(&mut audio, 0.06);
+        assert_eq!(audio.with_mixer(|mixer| mixer.sounds.len()), 0);
+    }
+
+    #[test]
+    fn missing_sounds_are_skipped() {
+        let mut audio = null_with_sounds(&[("empty", 0)]);
+        play_sound(&mut audio, "empty", 1.0, 1.0);
+        play_music(&mut audio, Some("empty"), 1.0);
+        assert_eq!(audio.with_mixer(|mixer| mixer.sounds.len() + mixer.music.len()), 0);
+    }
+
+    #[test]
+    fn music_crossfades() {
+        let mut audio = null_with_sounds(&[("a", 1000), ("b", 1000)]);
+        play_music(&mut audio, Some("a"), 1.0);
+        update_audio(&mut audio, 0.5);
+        let gains = music_gains(&mut audio);
+        assert_eq!(gains.len(), 1);
+        assert!((gains[0] - 0.5).abs() < 0.01);
+
+        play_music(&mut audio, Some("b"), 1.0);
+        update_audio(&mut audio, 0.25);
+        let gains = music_gains(&mut audio);
+        assert!((gains[0] - 0.25).abs() < 0.01);
+        assert!((gains[1] - 0.25).abs() < 0.01);
+
+        update_audio(&mut audio, 1.0);
+        assert_eq!(music_gains(&mut audio), vec![1.0]);
+    }
+
+    #[test]
+    fn same_music_keeps_playing() {
+        let mut audio = null_with_sounds(&[("a", 1000)]);
+        play_music(&mut audio, Some("a"), 0.0);
+        update_audio(&mut audio, 0.1);
+        play_music(&mut audio, Some("a"), 1.0);
+        assert_eq!(music_gains(&mut audio), vec![1.0]);
+
+        play_music(&mut audio, None, 0.5);
+        update_audio(&mut audio, 0.6);
+        assert!(music_gains(&mut audio).is_empty());
+    }
+
+    #[test]
+    fn volume_scales_the_output() {
+        let mut audio = null_with_sounds(&[("beep", 100)]);
+        set_volume(&mut audio, &AudioSettings { master: 0.5, music: 1.0, sfx: 0.5 });
+        play_sound(&mut audio, "beep", 1.0, 1.0);
+        let mut out = [0.0; 8];
+        audio.with_mixer(|mixer| mixer.mix(&mut out));
+        assert!(out.iter().all(|sample| (sample - 0.125).abs() < 1e-6));
+    }
+
+    fn listener_at(x: f32, y: f32) -> Listener {
+        Listener { x, y, half_width: 100.0 }
+    }
+
+    #[test]
+    fn sounds_are_panned_and_fade_with_distance() {
+        let listener = listener_at(0.0, 0.0);
+        assert_eq!(spatialize(&listener, 0.0, 0.0, 300.0), (1.0, 0.0));
+        assert_eq!(spatialize(&listener, 0.0, 100.0, 300.0), (1.0, 0.0));
+        let (gain, pan) = spatialize(&listener, 200.0, 0.0, 300.0);
+        assert!((gain - 0.5).abs() < 1e-6);
+        assert_eq!(pan, 1.0);
+        assert_eq!(spatialize(&listener, -50.0, 0.0, 300.0).1, -0.5);
+        assert_eq!(spatialize(&listener, 0.0, -400.0, 300.0).0, 0.0);
+    }
+
+    #[test]
+    fn positional_sounds_follow_the_camera() {
+        let mut audio = null_with_sounds(&[("beep", 100)]);
+        set_listener(&mut audio, &sdl2::rect::Rect::new(-100, 0, 640, 360), (200, 100));
+        assert_eq!(audio.listener, Listener { x: 200.0, y: 50.0, half_width: 100.0 });
+
+        play_sound_at(&mut audio, "beep", 1.0, 1.0, 300.0, 50.0);
+        let mut out = [0.0; 4];
+        audio.with_mixer(|mixer| mixer.mix(&mut out));
+        assert_eq!(out, [0.0, 0.5, 0.0, 0.5]);
+
+        play_sound_at(&mut audio, "beep", 1.0, 1.0, 2000.0, 50.0);
+        assert_eq!(audio.with_mixer(|mixer| mixer.sounds.len()), 1);
+    }
+
+    #[test]
+    fn ambient_sounds_follow_the_listener() {
+        let mut audio = null_with_sounds(&[("wind", 100)]);
+        let ambient = |sound: &str| AmbientSound {
+            sound: sound.to_owned(),
+            x: 0.0,
+            y: 0.0,
+            volume: 0.5,
+            radius: 300.0,
+        };
+        set_ambient_sounds(&mut audio, vec![ambient("wind")]);
+        audio.listener = listener_at(-50.0, 0.0);
+        update_audio(&mut audio, 0.0);
+        let placed = audio.with_mixer(|mixer| {
+            mixer.ambient.iter().map(|ambient| (ambient.volume, ambient.pan)).collect::<Vec<_>>()
+        });
+        assert_eq!(placed, vec![(0.5, 0.5)]);
+    }
+
+    #[test]
+    fn missing_ambient_sounds_are_left_out() {
+        let mut audio = null_with_sounds(&[("missing", 0), ("wind", 100)]);
+        let missing = AmbientSound { sound: "missing".to_owned(), x: 0.0, y: 0.0, volume: 1.0, radius: 10.0 };
+        let wind = AmbientSound { sound: "wind".to_owned(), x: 100.0, y: 0.0, volume: 0.5, radius: 600.0 };
+        set_ambient_sounds(&mut audio, vec![missing, wind.clone()]);
+        assert_eq!(audio.ambient, vec![wind]);
+        // mixing before any update must not touch an empty loop
+        let mut out = [0.0; 4];
+        audio.with_mixer(|mixer| mixer.mix(&mut out));
+
+        audio.listener = listener_at(0.0, 0.0);
+        update_audio(&mut audio, 0.0);
+        let placed = audio.with_mixer(|mixer| {
+            mixer.ambient.iter().map(|ambient| (ambient.volume, ambient.pan)).collect::<Vec<_>>()
+        });
+        assert_eq!(placed, vec![(0.5, 1.0)]);
+    }
+
+    #[test]
+    fn music_ducks_while_a_dialog_is_open() {
+        let mut audio = null_with_sounds(&[("a", 1000)]);
+        play_music(&mut audio, Some("a"), 0.0);
+        set_music_ducked(&mut audio, true);
+        update_audio(&mut audio, 0.1);
+        assert!((audio.with_mixer(|mixer| mixer.duck) - 0.8).abs() < 0.01);
+        update_audio(&mut audio, 1.0);
+        assert_eq!(audio.with_mixer(|mixer| mixer.duck), DUCK_VOLUME);
+
+        set_music_ducked(&mut audio, false);
+        update_audio(&mut audio, 1.0);
+        assert_eq!(audio.with_mixer(|mixer| mixer.duck), 1.0);
+    }
+}
@@ -0,0 +1,442 @@
+//! Checks every map under `res/` against the conventions the game relies on.
+//!
+//! Run from the project root with `cargo run --bin tomato-lint`,
+//! exits with status 1 when anything is wrong.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use json::JsonValue;
+use project_tomato::properties::{
+    FONT, INSPECT_ID, MAP_CHANGE, MAP_PROPERTIES, MUSIC, OBJECT_PROPERTIES, REQUIRED_PROPERTIES, SOUND, SPAWN_PLACE,
+    TEXT, TEXTURE,
+};
+use project_tomato::{
+    type_name, Property, DEFAULT_FONT, DIALOGUES_PATH, FONTS_PATH, PLURAL_FORMS, SPEAKERS_PATH, TRANSLATIONS_DIR,
+};
+use tiled::{LayerType, Properties, PropertyValue};
+
+const RES_DIR: &str = "res";
+/// Other translations are compared with this one
+const REFERENCE_LANG: &str = "en";
+const REQUIRED_LAYERS: &[&str] = &["Colliders", "PlayerSpawners"];
+
+struct MapChange {
+    object: u32,
+    target: String,
+    spawn: Option<i32>,
+}
+
+#[derive(Default)]
+struct MapInfo {
+    spawn_places: Vec<i32>,
+    map_changes: Vec<MapChange>,
+}
+
+struct Lint {
+    problems: usize,
+}
+
+impl Lint {
+    fn report(&mut self, location: &str, message: &str) {
+        println!("{}: {}", location, message);
+        self.problems += 1;
+    }
+}
+
+fn check_property_types(lint: &mut Lint, location: &str, properties: &Properties, expected: &[Property]) {
+    for property in expected {
+        if let Some(value) = properties.get(property.name) {
+            if !property.kind.accepts(value) {
+                lint.report(
+                    location,
+                    &format!(
+                        "property \"{}\" should be {}, found {}",
+                        property.name,
+                        property.kind.name(),
+                        type_name(value)
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn find_files(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_files(&path, extension, files);
+        } else if path.extension().map_or(false, |e| e == extension) {
+            files.push(path);
+        }
+    }
+}
+
+fn load_json(lint: &mut Lint, path: &Path) -> Option<JsonValue> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) => {
+            lint.report(&path.display().to_string(), &e.to_string());
+            return None;
+        }
+    };
+    match json::parse(&data) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            lint.report(&path.display().to_string(), &e.to_string());
+            None
+        }
+    }
+}
+
+/// Reports keys missing from or not in the reference translation, lists keys that have the
+/// reference text as possibly untranslated without counting them as problems
+fn check_translations(lint: &mut Lint, translations: &[(String, JsonValue)]) {
+    let reference = match translations.iter().find(|(lang, _)| lang == REFERENCE_LANG) {
+        Some((_, reference)) => reference,
+        None => {
+            lint.report(TRANSLATIONS_DIR, &format!("no {}.json to compare with", REFERENCE_LANG));
+            return;
+        }
+    };
+    for (lang, translation) in translations.iter().filter(|(lang, _)| lang != REFERENCE_LANG) {
+        let location = format!("{}{}.json", TRANSLATIONS_DIR, lang);
+        let mut untranslated = vec![];
+        for (key, text) in reference.entries() {
+            let translated = &translation[key];
+            if translated.is_null() {
+                lint.report(&location, &format!("missing key \"{}\"", key));
+            } else if translated == text || translated.as_str() == Some("") {
+                untranslated.push(key);
+            }
+        }
+        for (key, _) in translation.entries() {
+            if !reference.has_key(key) {
+                lint.report(&location, &format!("extra key \"{}\"", key));
+            }
+        }
+        if !untranslated.is_empty() {
+            println!("{}: same as {}, untranslated? {}", location, REFERENCE_LANG, untranslated.join(", "));
+        }
+    }
+}
+
+/// Translations are strings or objects of plural forms, see `Translator`
+fn check_translation_values(lint: &mut Lint, translations: &[(String, JsonValue)]) {
+    for (lang, translation) in translations {
+        let location = format!("{}{}.json", TRANSLATIONS_DIR, lang);
+        let forms = PLURAL_FORMS
+            .iter()
+            .find(|(name, _)| name == lang)
+            .map_or(&["other"][..], |(_, forms)| *forms);
+        for (key, value) in translation.entries() {
+            if value.is_string() {
+                continue;
+            }
+            if !value.is_object() {
+                lint.report(&location, &format!("\"{}\" should be a string or plural forms", key));
+                continue;
+            }
+            for (form, text) in value.entries() {
+                if !text.is_string() {
+                    lint.report(&location, &format!("\"{}\" form \"{}\" should be a string", key, form));
+                }
+            }
+            if value.has_key("other") {
+                continue;
+            }
+            for form in forms.iter().filter(|form| !value.has_key(form)) {
+                lint.report(&location, &format!("\"{}\" is missing plural form \"{}\"", key, form));
+            }
+        }
+    }
+}
+
+/// Reports a missing translation for `key` in every language that lacks it
+fn check_text_key(
+    lint: &mut Lint,
+    location: &str,
+    key: &str,
+    translations: &[(String, JsonValue)],
+) {
+    for (lang, translation) in translations {
+        if translation[key].is_null() {
+            lint.report(location, &format!("text key \"{}\" is missing in {}", key, lang));
+        }
+    }
+}
+
+/// Dialogues are either a flat `texts` array or `start` and `nodes` with speakers, choices and jumps
+fn check_dialogue(
+    lint: &mut Lint,
+    id: &str,
+    dialogue: &JsonValue,
+    speakers: &JsonValue,
+    translations: &[(String, JsonValue)],
+) {
+    let location = format!("{}, dialogue {}", DIALOGUES_PATH, id);
+    if id.parse::<u32>().is_err() {
+        lint.report(&location, "dialogue ids should be numbers");
+    }
+    if dialogue["texts"].is_array() {
+        for key in dialogue["texts"].members() {
+            match key.as_str() {
+                Some(key) => check_text_key(lint, &location, key, translations),
+                None => lint.report(&location, "text keys should be strings"),
+            }
+        }
+        return;
+    }
+    let nodes = &dialogue["nodes"];
+    // jumps are a node id or an array of `{"if": conditions, "node": id}`
+    let check_next = |lint: &mut Lint, location: &str, next: &JsonValue| {
+        let targets: Vec<&JsonValue> = if next.is_array() {
+            next.members().map(|jump| &jump["node"]).collect()
+        } else {
+            vec![next]
+        };
+        for target in targets {
+            match target.as_str() {
+                Some(node) if !nodes.has_key(node) => {
+                    lint.report(location, &format!("node \"{}\" does not exist", node))
+                }
+                Some(_) => {}
+                None if target.is_null() && !next.is_array() => {}
+                None => lint.report(location, "jump without a node"),
+            }
+        }
+    };
+    if dialogue["start"].is_null() {
+        lint.report(&location, "missing \"texts\" or \"start\"");
+    }
+    check_next(lint, &location, &dialogue["start"]);
+    for (node_id, node) in nodes.entries() {
+        let location = format!("{}, node \"{}\"", location, node_id);
+        match node["text"].as_str() {
+            Some(key) => check_text_key(lint, &location, key, translations),
+            None => lint.report(&location, "missing \"text\""),
+        }
+        if let Some(speaker) = node["speaker"].as_str() {
+            if !speakers.has_key(speaker) {
+                lint.report(&location, &format!("speaker \"{}\" is not in {}", speaker, SPEAKERS_PATH));
+            }
+        }
+        check_next(lint, &location, &node["next"]);
+        for choice in node["choices"].members() {
+            match choice["text"].as_str() {
+                Some(key) => check_text_key(lint, &location, key, translations),
+                None => lint.report(&location, "choice without \"text\""),
+            }
+            check_next(lint, &location, &choice["next"]);
+        }
+    }
+}
+
+fn check_map(
+    lint: &mut Lint,
+    path: &Path,
+    map: &tiled::Map,
+    dialogues: &JsonValue,
+    translations: &[(String, JsonValue)],
+    fonts: &[String],
+) -> MapInfo {
+    let map_name = path.display().to_string();
+    let mut info = MapInfo::default();
+
+    for required in REQUIRED_LAYERS {
+        if !map.layers().any(|layer| layer.name == *required) {
+            lint.report(&map_name, &format!("missing layer \"{}\"", required));
+        }
+    }
+    check_property_types(lint, &map_name, &map.properties, MAP_PROPERTIES);
+    if let Some(PropertyValue::StringValue(music)) = map.properties.get(MUSIC.name) {
+        if !Path::new(music).exists() {
+            lint.report(&map_name, &format!("music {} does not exist", music));
+        }
+    }
+
+    for layer in map.layers() {
+        let objects = match layer.layer_type() {
+            LayerType::ObjectLayer(objects) => objects,
+            _ => continue,
+        };
+        let required_properties = REQUIRED_PROPERTIES
+            .iter()
+            .find(|(name, _)| *name == layer.name)
+            .map_or(&[][..], |(_, properties)| *properties);
+
+        for obj in objects.objects() {
+            let location = format!("{}, layer \"{}\", object {}", map_name, layer.name, obj.id());
+
+            for property in required_properties {
+                if !obj.properties.contains_key(property.name) {
+                    lint.report(&location, &format!("missing property \"{}\"", property.name));
+                }
+            }
+            check_property_types(lint, &location, &obj.properties, OBJECT_PROPERTIES);
+
+            let spawn = match obj.properties.get(SPAWN_PLACE.name) {
+                Some(PropertyValue::IntValue(spawn)) => Some(*spawn),
+                _ => None,
+            };
+            if layer.name == "PlayerSpawners" {
+                if let Some(spawn) = spawn {
+                    if info.spawn_places.contains(&spawn) {
+                        lint.report(&location, &format!("spawn place {} is used twice", spawn));
+                    }
+                    info.spawn_places.push(spawn);
+                }
+            }
+            if let Some(PropertyValue::StringValue(target)) = obj.properties.get(MAP_CHANGE.name) {
+                if spawn.is_none() {
+                    lint.report(&location, "map change without \"spawn place\"");
+                }
+                info.map_changes.push(MapChange {
+                    object: obj.id(),
+                    target: target.clone(),
+                    spawn,
+                });
+            }
+            if let Some(PropertyValue::IntValue(id)) = obj.properties.get(INSPECT_ID.name) {
+                let dialogue = &dialogues[id.to_string()];
+                if dialogue.is_null() {
+                    lint.report(&location, &format!("inspect id {} is not in {}", id, DIALOGUES_PATH));
+                }
+            }
+            if let Some(PropertyValue::StringValue(texture)) = obj.properties.get(TEXTURE.name) {
+                if !Path::new(texture).exists() {
+                    lint.report(&location, &format!("texture {} does not exist", texture));
+                }
+            }
+            if let Some(PropertyValue::StringValue(sound)) = obj.properties.get(SOUND.name) {
+                if !Path::new(sound).exists() {
+                    lint.report(&location, &format!("sound {} does not exist", sound));
+                }
+            }
+            if let Some(PropertyValue::StringValue(key)) = obj.properties.get(TEXT.name) {
+                check_text_key(lint, &location, key, translations);
+            }
+            // fonts are picked by name or by their index in the registry
+            match obj.properties.get(FONT.name) {
+                Some(PropertyValue::StringValue(font)) if !fonts.contains(font) => {
+                    lint.report(&location, &format!("font {} is not in {}", font, FONTS_PATH));
+                }
+                Some(PropertyValue::IntValue(index)) if *index < 0 || *index as usize >= fonts.len() => {
+                    lint.report(&location, &format!("there is no font {}", index));
+                }
+                _ => {}
+            }
+        }
+    }
+    info
+}
+
+fn main() {
+    let mut lint = Lint { problems: 0 };
+
+    let dialogues = load_json(&mut lint, Path::new(DIALOGUES_PATH)).unwrap_or(JsonValue::Null);
+    let speakers = load_json(&mut lint, Path::new(SPEAKERS_PATH)).unwrap_or(JsonValue::Null);
+    let mut translation_files = vec![];
+    find_files(Path::new(TRANSLATIONS_DIR), "json", &mut translation_files);
+    translation_files.sort();
+    let translations: Vec<(String, JsonValue)> = translation_files
+        .iter()
+        .filter_map(|path| {
+            let lang = path.file_stem()?.to_string_lossy().into_owned();
+            Some((lang, load_json(&mut lint, path)?))
+        })
+        .collect();
+    if translations.is_empty() {
+        lint.report(TRANSLATIONS_DIR, "no translations found");
+    } else {
+        check_translations(&mut lint, &translations);
+        check_translation_values(&mut lint, &translations);
+    }
+
+    let fonts = load_json(&mut lint, Path::new(FONTS_PATH)).unwrap_or(JsonValue::Null);
+    let mut font_names = vec![DEFAULT_FONT.to_owned()];
+    for (name, path) in fonts["fonts"].entries() {
+        let location = format!("{}, font {}", FONTS_PATH, name);
+        match path.as_str() {
+            Some(path) if !Path::new(path).exists() => {
+                lint.report(&location, &format!("font file {} does not exist", path))
+            }
+            Some(_) => {}
+            None => lint.report(&location, "should be a path"),
+        }
+        font_names.push(name.to_owned());
+    }
+    if let Some(fallback) = fonts["fallback"].as_str() {
+        if !font_names.iter().any(|name| name == fallback) {
+            lint.report(FONTS_PATH, &format!("fallback font {} is not in \"fonts\"", fallback));
+        }
+    }
+
+    for (id, dialogue) in dialogues.entries() {
+        check_dialogue(&mut lint, id, dialogue, &speakers, &translations);
+    }
+    for (id, speaker) in speakers.entries() {
+        let location = format!("{}, speaker {}", SPEAKERS_PATH, id);
+        check_text_key(&mut lint, &location, speaker["name"].as_str().unwrap_or(id), &translations);
+        if let Some(texture) = speaker["portrait"]["texture"].as_str() {
+            if !Path::new(texture).exists() {
+                lint.report(&location, &format!("texture {} does not exist", texture));
+            }
+        }
+        if let Some(sound) = speaker["voice"]["sound"].as_str() {
+            if !Path::new(sound).exists() {
+                lint.report(&location, &format!("sound {} does not exist", sound));
+            }
+        }
+    }
+
+    let mut map_paths = vec![];
+    find_files(Path::new(RES_DIR), "tmx", &mut map_paths);
+    map_paths.sort();
+
+    let mut loader = tiled::Loader::new();
+    let mut maps: HashMap<PathBuf, MapInfo> = HashMap::new();
+    for path in map_paths.iter() {
+        match loader.load_tmx_map(path) {
+            Ok(map) => {
+                let info = check_map(&mut lint, path, &map, &dialogues, &translations, &font_names);
+                maps.insert(path.clone(), info);
+            }
+            Err(e) => lint.report(&path.display().to_string(), &e.to_string()),
+        }
+    }
+
+    // map change targets are only known once every map is loaded
+    for path in map_paths.iter() {
+        let info = if let Some(info) = maps.get(path) { info } else { continue };
+        for change in info.map_changes.iter() {
+            let location = format!("{}, object {}", path.display(), change.object);
+            let target = match maps.get(Path::new(&change.target)) {
+                Some(target) => target,
+                None => {
+                    lint.report(&location, &format!("map change target {} does not exist", change.target));
+                    continue;
+                }
+            };
+            if let Some(spawn) = change.spawn {
+                if !target.spawn_places.contains(&spawn) {
+                    lint.report(
+                        &location,
+                        &format!("{} has no spawn place {}", change.target, spawn),
+                    );
+                }
+            }
+        }
+    }
+
+    if lint.problems > 0 {
+        println!("{} problem(s) found", lint.problems);
+        std::process::exit(1);
+    }
+    println!("{} map(s) ok", maps.len());
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+
+use json::JsonValue;
+use sdl2::controller::Button;
+use sdl2::keyboard::Keycode;
+
+use crate::gamepad::InputDevice;
+
+pub const BINDINGS_PATH: &str = "bindings.json";
+
+/// Keys with a fixed meaning that can not be bound: the console and the debug overlay
+pub const RESERVED_KEYS: &[Keycode] = &[Keycode::Backquote, Keycode::F3];
+/// Back stops waiting for a new button on the controls screen, Guide belongs to the system
+pub const RESERVED_BUTTONS: &[Button] = &[Button::Back, Button::Guide];
+
+/// What the player wants to do, game code reads actions and the player picks the keys
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum InputAction {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Jump,
+    Interact,
+    Confirm,
+    Cancel,
+    Pause,
+}
+
+pub const ACTIONS: [InputAction; 9] = [
+    InputAction::MoveLeft,
+    InputAction::MoveRight,
+    InputAction::MoveUp,
+    InputAction::MoveDown,
+    InputAction::Jump,
+    InputAction::Interact,
+    InputAction::Confirm,
+    InputAction::Cancel,
+    InputAction::Pause,
+];
+
+/// Where an action is read, actions that are never read at the same time can share keys
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum InputContext {
+    Gameplay,
+    /// Menus, dialogues and the error screen
+    Menu,
+}
+
+/// Actions meant to share a key: Up interacts and flies up in noclip
+const SHARED_ACTIONS: &[(InputAction, InputAction)] = &[(InputAction::MoveUp, InputAction::Interact)];
+
+impl InputAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputAction::MoveLeft => "move_left",
+            InputAction::MoveRight => "move_right",
+            InputAction::MoveUp => "move_up",
+            InputAction::MoveDown => "move_down",
+            InputAction::Jump => "jump",
+            InputAction::Interact => "interact",
+            InputAction::Confirm => "confirm",
+            InputAction::Cancel => "cancel",
+            InputAction::Pause => "pause",
+        }
+    }
+
+    /// Translation key of the name shown on the controls screen
+    pub fn label(&self) -> &'static str {
+        match self {
+            InputAction::MoveLeft => "action-move-left",
+            InputAction::MoveRight => "action-move-right",
+            InputAction::MoveUp => "action-move-up",
+            InputAction::MoveDown => "action-move-down",
+            InputAction::Jump => "action-jump",
+            InputAction::Interact => "action-interact",
+            InputAction::Confirm => "action-confirm",
+            InputAction::Cancel => "action-cancel",
+            InputAction::Pause => "action-pause",
+        }
+    }
+
+    fn contexts(&self) -> &'static [InputContext] {
+        match self {
+            InputAction::MoveLeft | InputAction::MoveRight | InputAction::MoveUp | InputAction::MoveDown => {
+                &[InputContext::Gameplay, InputContext::Menu]
+            }
+            InputAction::Jump | InputAction::Interact => &[InputContext::Gameplay],
+            InputAction::Confirm | InputAction::Cancel => &[InputContext::Menu],
+            InputAction::Pause => &[InputContext::Gameplay, InputContext::Menu],
+        }
+    }
+
+    fn default_keys(&self) -> &'static [Keycode] {
+        match self {
+            InputAction::MoveLeft => &[Keycode::Left],
+            InputAction::MoveRight => &[Keycode::Right],
+            InputAction::MoveUp => &[Keycode::Up],
+            InputAction::MoveDown => &[Keycode::Down],
+            InputAction::Jump => &[Keycode::Z],
+            InputAction::Interact => &[Keycode::Up],
+            InputAction::Confirm => &[Keycode::Z, Keycode::Return],
+            InputAction::Cancel => &[Keycode::X],
+            InputAction::Pause => &[Keycode::Escape],
+        }
+    }
+
+    fn default_buttons(&self) -> &'static [Button] {
+        match self {
+            InputAction::MoveLeft => &[Button::DPadLeft],
+            InputAction::MoveRight => &[Button::DPadRight],
+            InputAction::MoveUp => &[Button::DPadUp],
+            InputAction::MoveDown => &[Button::DPadDown],
+            InputAction::Jump => &[Button::A],
+            InputAction::Interact => &[Button::Y, Button::DPadUp],
+            InputAction::Confirm => &[Button::A],
+            InputAction::Cancel => &[Button::B],
+            InputAction::Pause => &[Button::Start],
+        }
+    }
+
+    /// Whether the actions can be wanted at the same time
+    fn overlaps(&self, other: InputAction) -> bool {
+        let shared = SHARED_ACTIONS
+            .iter()
+            .any(|pair| *pair == (*self, other) || *pair == (other, *self));
+        !shared && self.contexts().iter().any(|context| other.contexts().contains(context))
+    }
+}
+
+/// A key or a gamepad button
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Binding {
+    Key(Keycode),
+    Button(Button),
+}
+
+impl Binding {
+    /// SDL name, like `Left` or `dpup`
+    pub fn name(&self) -> String {
+        match self {
+            Binding::Key(key) => key.name(),
+            Binding::Button(button) => button.string(),
+        }
+    }
+
+    pub fn device(&self) -> InputDevice {
+        match self {
+            Binding::Key(_) => InputDevice::Keyboard,
+            Binding::Button(_) => InputDevice::Gamepad,
+        }
+    }
+
+    fn is_reserved(&self) -> bool {
+        match self {
+            Binding::Key(key) => RESERVED_KEYS.contains(key),
+            Binding::Button(button) => RESERVED_BUTTONS.contains(button),
+        }
+    }
+}
+
+/// A key or button that does two things at once
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Conflict {
+    pub binding: Binding,
+    pub action: InputAction,
+    /// `None` when the binding is reserved
+    pub other: Option<InputAction>,
+}
+
+/// Keys and gamepad buttons of every action, saved in `bindings.json` with SDL names as
+/// `{"jump": ["Z"], ..., "gamepad": {"jump": ["a"], ...}}`
+#[derive(Clone, PartialEq, Debug)]
+pub struct Bindings {
+    keys: HashMap<InputAction, Vec<Keycode>>,
+    buttons: HashMap<InputAction, Vec<Button>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            keys: ACTIONS
+                .iter()
+                .map(|action| (*action, action.default_keys().to_vec()))
+                .collect(),
+            buttons: ACTIONS
+                .iter()
+                .map(|action| (*action, action.default_buttons().to_vec()))
+                .collect(),
+        }
+    }
+}
+
+/// Names in `value` parsed with `parse`, `None` when the list is missing or invalid
+fn parse_names<T>(value: &JsonValue, action: InputAction, parse: impl Fn(&str) -> Option<T>) -> Option<Vec<T>> {
+    if value.is_null() {
+        return None;
+    }
+    if !value.is_array() {
+        println!("Bindings of {} in {} should be a list", action.name(), BINDINGS_PATH);
+        return None;
+    }
+    let names: Option<Vec<T>> = value
+        .members()
+        .map(|name| {
+            let parsed = name.as_str().and_then(&parse);
+            if parsed.is_none() {
+                println!("Unknown key or button {} for {} in {}", name, action.name(), BINDINGS_PATH);
+            }
+            parsed
+        })
+        .collect();
+    match names {
+        // every action needs a binding, an unbound pause would lock the player out of the menu
+        Some(names) if names.is_empty() => {
+            println!("Nothing bound to {} in {}", action.name(), BINDINGS_PATH);
+            None
+        }
+        names => names,
+    }
+}
+
+impl Bindings {
+    /// Loads `bindings.json`, actions that are missing, empty or invalid keep their default bindings
+    pub fn load() -> Self {
+        let data = match std::fs::read_to_string(BINDINGS_PATH) {
+            Ok(data) => data,
+            Err(_) => return Self::default(),
+        };
+        let bindings = match json::parse(&data) {
+            Ok(data) => Self::from_json(&data),
+            Err(e) => {
+                println!("Invalid {}: {}", BINDINGS_PATH, e);
+                return Self::default();
+            }
+        };
+        for conflict in bindings.conflicts() {
+            match conflict.other {
+                Some(other) => println!(
+                    "{} is bound to both {} and {}",
+                    conflict.binding.name(),
+                    conflict.action.name(),
+                    other.name()
+                ),
+                None => println!("{} of {} is reserved", conflict.binding.name(), conflict.action.name()),
+            }
+        }
+        bindings
+    }
+
+    pub fn from_json(data: &JsonValue) -> Self {
+        let mut bindings = Self::default();
+        for action in ACTIONS {
+            if let Some(keys) = parse_names(&data[action.name()], action, Keycode::from_name) {
+                bindings.keys.insert(action, keys);
+            }
+            if let Some(buttons) = parse_names(&data["gamepad"][action.name()], action, Button::from_string) {
+                bindings.buttons.insert(action, buttons);
+            }
+        }
+        bindings
+    }
+
+    pub fn save(&self) {
+        let mut data = JsonValue::new_object();
+        let mut gamepad = JsonValue::new_object();
+        for action in ACTIONS {
+            let keys: Vec<JsonValue> = self.keys(action).iter().map(|key| key.name().into()).collect();
+            data[action.name()] = JsonValue::Array(keys);
+            let buttons: Vec<JsonValue> = self.buttons(action).iter().map(|button| button.string().into()).collect();
+            gamepad[action.name()] = JsonValue::Array(buttons);
+        }
+        data["gamepad"] = gamepad;
+        if let Err(e) = std::fs::write(BINDINGS_PATH, data.pretty(4)) {
+            println!("Failed to save {}: {}", BINDINGS_PATH, e);
+        }
+    }
+
+    pub fn keys(&self, action: InputAction) -> &[Keycode] {
+        self.keys.get(&action).map_or(&[], |keys| keys.as_slice())
+    }
+
+    pub fn buttons(&self, action: InputAction) -> &[Button] {
+        self.buttons.get(&action).map_or(&[], |buttons| buttons.as_slice())
+    }
+
+    /// Keys or buttons of `action` on `device`
+    pub fn bindings(&self, action: InputAction, device: InputDevice) -> Vec<Binding> {
+        match device {
+            InputDevice::Keyboard => self.keys(action).iter().map(|key| Binding::Key(*key)).collect(),
+            InputDevice::Gamepad => self.buttons(action).iter().map(|button| Binding::Button(*button)).collect(),
+        }
+    }
+
+    /// Replaces the keys or the buttons of `action` with `binding`, the other device keeps its bindings
+    pub fn bind(&mut self, action: InputAction, binding: Binding) {
+        match binding {
+            Binding::Key(key) => {
+                self.keys.insert(action, vec![key]);
+            }
+            Binding::Button(button) => {
+                self.buttons.insert(action, vec![button]);
+            }
+        }
+    }
+
+    /// Keys and buttons bound to actions that can be wanted at the same time, and reserved ones
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts = vec![];
+        for device in [InputDevice::Keyboard, InputDevice::Gamepad] {
+            for (i, action) in ACTIONS.iter().enumerate() {
+                for binding in self.bindings(*action, device) {
+                    if binding.is_reserved() {
+                        conflicts.push(Conflict { binding, action: *action, other: None });
+                    }
+                    for other in ACTIONS[i + 1..].iter() {
+                        if action.overlaps(*other) && self.bindings(*other, device).contains(&binding) {
+                            conflicts.push(Conflict { binding, action: *action, other: Some(*other) });
+                        }
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings_have_no_conflicts() {
+        assert_eq!(Bindings::default().conflicts(), vec![]);
+    }
+
+    #[test]
+    fn keys_conflict_only_when_actions_overlap() {
+        let mut bindings = Bindings::default();
+        // jumping is never read in menus, where X cancels
+        bindings.bind(InputAction::Jump, Binding::Key(Keycode::X));
+        bindings.bind(InputAction::Jump, Binding::Button(Button::B));
+        assert_eq!(bindings.conflicts(), vec![]);
+
+        bindings.bind(InputAction::Interact, Binding::Key(Keycode::X));
+        bindings.bind(InputAction::Pause, Binding::Key(Keycode::Backquote));
+        bindings.bind(InputAction::Cancel, Binding::Button(Button::A));
+        assert_eq!(
+            bindings.conflicts(),
+            vec![
+                Conflict {
+                    binding: Binding::Key(Keycode::X),
+                    action: InputAction::Jump,
+                    other: Some(InputAction::Interact),
+                },
+                Conflict { binding: Binding::Key(Keycode::Backquote), action: InputAction::Pause, other: None },
+                Conflict {
+                    binding: Binding::Button(Button::A),
+                    action: InputAction::Confirm,
+                    other: Some(InputAction::Cancel),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_bindings_keep_the_defaults() {
+        let data = json::object! {
+            "jump": ["Space"],
+            "confirm": ["Return", "No such key"],
+            "cancel": "X",
+            "pause": [],
+            "gamepad": { "jump": ["x"], "cancel": ["nope"] },
+        };
+        let bindings = Bindings::from_json(&data);
+        assert_eq!(bindings.keys(InputAction::Jump), &[Keycode::Space]);
+        assert_eq!(bindings.keys(InputAction::Confirm), &[Keycode::Z, Keycode::Return]);
+        assert_eq!(bindings.keys(InputAction::Cancel), &[Keycode::X]);
+        assert_eq!(bindings.keys(InputAction::Pause), &[Keycode::Escape]);
+        assert_eq!(bindings.keys(InputAction::MoveLeft), &[Keycode::Left]);
+        assert_eq!(bindings.buttons(InputAction::Jump), &[Button::X]);
+        assert_eq!(bindings.buttons(InputAction::Cancel), &[Button::B]);
+    }
+}
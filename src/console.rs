@@ -0,0 +1,255 @@
+use fontdue::layout::{Layout, LayoutSettings, VerticalAlign};
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
+
+use crate::{get_key_pressed, render::RenderingState, InputState};
+
+const MAX_LOG_LINES: usize = 100;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ConsoleCommand {
+    Help,
+    /// Map path and spawn number
+    LoadMap(String, u32),
+    Teleport(f32, f32),
+    Noclip,
+    God,
+    Lang(String),
+    /// Enemy type and optional position, defaults to next to the player
+    Spawn(String, Option<(f32, f32)>),
+    TimeScale(f32),
+    ReloadDialogues,
+    /// Lists translation keys that were looked up and missing
+    MissingTranslations,
+}
+
+pub const HELP: &str = "map <path> [spawn]\ntp <x> <y>\nnoclip\ngod\nlang <code>\nspawn <type> [x y]\ntimescale <scale>\nreload dialogues\nmissing";
+
+pub struct ConsoleState {
+    pub open: bool,
+    pub line: String,
+    pub log: Vec<String>,
+    pub history: Vec<String>,
+    history_index: Option<usize>,
+    layout: Layout<Color>,
+}
+
+impl ConsoleState {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            line: "".to_owned(),
+            log: vec![],
+            history: vec![],
+            history_index: None,
+            layout: Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown),
+        }
+    }
+}
+
+pub fn console_print(console: &mut ConsoleState, text: &str) {
+    for line in text.lines() {
+        console.log.push(line.to_owned());
+    }
+    if console.log.len() > MAX_LOG_LINES {
+        let overflow = console.log.len() - MAX_LOG_LINES;
+        console.log.drain(..overflow);
+    }
+}
+
+pub fn parse_command(line: &str) -> Result<ConsoleCommand, String> {
+    let args: Vec<&str> = line.split_whitespace().collect();
+    fn number<T: std::str::FromStr>(arg: Option<&&str>, name: &str) -> Result<T, String> {
+        arg.ok_or(format!("missing {}", name))?
+            .parse()
+            .map_err(|_| format!("invalid {}", name))
+    }
+    let max_args = match args.first().copied() {
+        Some("help" | "noclip" | "god" | "missing") => 1,
+        Some("lang" | "timescale" | "reload") => 2,
+        Some("map" | "tp") => 3,
+        Some("spawn") => 4,
+        _ => args.len(),
+    };
+    if args.len() > max_args {
+        return Err(format!("too many arguments for {}", args[0]));
+    }
+    match args.first().copied() {
+        Some("help") => Ok(ConsoleCommand::Help),
+        Some("map") => {
+            let path = args.get(1).ok_or("missing path")?.to_string();
+            let spawn = if args.len() > 2 { number(args.get(2), "spawn")? } else { 0 };
+            Ok(ConsoleCommand::LoadMap(path, spawn))
+        }
+        Some("tp") => Ok(ConsoleCommand::Teleport(
+            number(args.get(1), "x")?,
+            number(args.get(2), "y")?,
+        )),
+        Some("noclip") => Ok(ConsoleCommand::Noclip),
+        Some("god") => Ok(ConsoleCommand::God),
+        Some("lang") => Ok(ConsoleCommand::Lang(
+            args.get(1).ok_or("missing language code")?.to_string(),
+        )),
+        Some("spawn") => {
+            let kind = args.get(1).ok_or("missing enemy type")?.to_string();
+            let position = if args.len() > 2 {
+                Some((number(args.get(2), "x")?, number(args.get(3), "y")?))
+            } else {
+                None
+            };
+            Ok(ConsoleCommand::Spawn(kind, position))
+        }
+        Some("timescale") => Ok(ConsoleCommand::TimeScale(number(args.get(1), "scale")?)),
+        Some("reload") if args.get(1) == Some(&"dialogues") => Ok(ConsoleCommand::ReloadDialogues),
+        Some("missing") => Ok(ConsoleCommand::MissingTranslations),
+        Some(other) => Err(format!("unknown command: {}", other)),
+        None => Err("".to_owned()),
+    }
+}
+
+/// Toggles the console with ` and handles typing, returns a command when Enter is pressed
+pub fn update_console(console: &mut ConsoleState, input: &InputState) -> Option<ConsoleCommand> {
+    if get_key_pressed(Keycode::Backquote, input) {
+        console.open = !console.open;
+        return None;
+    }
+    if !console.open {
+        return None;
+    }
+    console
+        .line
+        .extend(input.text_input.chars().filter(|c| *c != '`'));
+    if get_key_pressed(Keycode::Backspace, input) {
+        console.line.pop();
+    }
+    if get_key_pressed(Keycode::Up, input) && !console.history.is_empty() {
+        let index = match console.history_index {
+            Some(index) => index.saturating_sub(1),
+            None => console.history.len() - 1,
+        };
+        console.history_index = Some(index);
+        console.line = console.history[index].clone();
+    }
+    if get_key_pressed(Keycode::Down, input) {
+        if let Some(index) = console.history_index {
+            if index + 1 < console.history.len() {
+                console.history_index = Some(index + 1);
+                console.line = console.history[index + 1].clone();
+            } else {
+                console.history_index = None;
+                console.line.clear();
+            }
+        }
+    }
+    if get_key_pressed(Keycode::Return, input) || get_key_pressed(Keycode::KpEnter, input) {
+        let line = std::mem::take(&mut console.line);
+        console.history_index = None;
+        if line.trim().is_empty() {
+            return None;
+        }
+        console_print(console, &format!("> {}", line));
+        console.history.push(line.clone());
+        match parse_command(&line) {
+            Ok(command) => return Some(command),
+            Err(e) => console_print(console, &e),
+        }
+    }
+    None
+}
+
+pub fn render_console(render: &mut RenderingState, console: &mut ConsoleState) {
+    if !console.open {
+        return;
+    }
+    let (canvas_w, canvas_h) = render.canvas.logical_size();
+    let height = canvas_h / 2;
+    let line_height = 8u32;
+    let margin = 4u32;
+
+    render.canvas.set_blend_mode(BlendMode::Blend);
+    render.canvas.set_draw_color(Color::RGBA(0, 0, 0, 200));
+    render.canvas.fill_rect(Rect::new(0, 0, canvas_w, height)).unwrap();
+    render.canvas.set_blend_mode(BlendMode::None);
+    render.canvas.set_draw_color(Color::GREEN);
+    render
+        .canvas
+        .draw_line((0, height as i32), (canvas_w as i32, height as i32))
+        .unwrap();
+
+    // newest lines at the bottom, right above the input line
+    let visible = ((height - 2 * margin) / line_height) as usize;
+    let start = console.log.len().saturating_sub(visible);
+    let mut text = console.log[start..].join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    console.layout.reset(&LayoutSettings {
+        x: margin as f32,
+        y: margin as f32,
+        max_width: Some((canvas_w - 2 * margin) as f32),
+        max_height: Some((height - 2 * margin) as f32),
+        vertical_align: VerticalAlign::Bottom,
+        ..LayoutSettings::default()
+    });
+    render.fonts.append(&mut console.layout, &text, 8.0, 0, Color::WHITE);
+    render.fonts.append(&mut console.layout, &format!("> {}_", console.line), 8.0, 0, Color::YELLOW);
+    render
+        .font_texture
+        .draw_text(&mut render.canvas, render.fonts.as_slice(), console.layout.glyphs())
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse_command("help"), Ok(ConsoleCommand::Help));
+        assert_eq!(parse_command("map res/level2.tmx"), Ok(ConsoleCommand::LoadMap("res/level2.tmx".to_owned(), 0)));
+        assert_eq!(parse_command("  map res/level2.tmx 3 "), Ok(ConsoleCommand::LoadMap("res/level2.tmx".to_owned(), 3)));
+        assert_eq!(parse_command("tp 10 -20.5"), Ok(ConsoleCommand::Teleport(10.0, -20.5)));
+        assert_eq!(parse_command("noclip"), Ok(ConsoleCommand::Noclip));
+        assert_eq!(parse_command("god"), Ok(ConsoleCommand::God));
+        assert_eq!(parse_command("lang pl"), Ok(ConsoleCommand::Lang("pl".to_owned())));
+        assert_eq!(parse_command("spawn gumba"), Ok(ConsoleCommand::Spawn("gumba".to_owned(), None)));
+        assert_eq!(parse_command("spawn gumba 1 2"), Ok(ConsoleCommand::Spawn("gumba".to_owned(), Some((1.0, 2.0)))));
+        assert_eq!(parse_command("timescale 0.5"), Ok(ConsoleCommand::TimeScale(0.5)));
+        assert_eq!(parse_command("reload dialogues"), Ok(ConsoleCommand::ReloadDialogues));
+        assert_eq!(parse_command("missing"), Ok(ConsoleCommand::MissingTranslations));
+    }
+
+    #[test]
+    fn reports_missing_arguments() {
+        assert_eq!(parse_command("map"), Err("missing path".to_owned()));
+        assert_eq!(parse_command("tp"), Err("missing x".to_owned()));
+        assert_eq!(parse_command("tp 10"), Err("missing y".to_owned()));
+        assert_eq!(parse_command("lang"), Err("missing language code".to_owned()));
+        assert_eq!(parse_command("spawn"), Err("missing enemy type".to_owned()));
+        assert_eq!(parse_command("spawn gumba 1"), Err("missing y".to_owned()));
+        assert_eq!(parse_command("timescale"), Err("missing scale".to_owned()));
+        assert_eq!(parse_command("reload"), Err("unknown command: reload".to_owned()));
+        assert_eq!(parse_command(""), Err("".to_owned()));
+    }
+
+    #[test]
+    fn rejects_extra_arguments() {
+        assert_eq!(parse_command("tp 1 2 3"), Err("too many arguments for tp".to_owned()));
+        assert_eq!(parse_command("god mode"), Err("too many arguments for god".to_owned()));
+        assert_eq!(parse_command("map a.tmx 1 2"), Err("too many arguments for map".to_owned()));
+        assert_eq!(parse_command("spawn gumba 1 2 3"), Err("too many arguments for spawn".to_owned()));
+        assert_eq!(parse_command("dance now"), Err("unknown command: dance".to_owned()));
+    }
+
+    #[test]
+    fn rejects_non_numeric_arguments() {
+        assert_eq!(parse_command("tp ten 20"), Err("invalid x".to_owned()));
+        assert_eq!(parse_command("tp 10 twenty"), Err("invalid y".to_owned()));
+        assert_eq!(parse_command("spawn gumba 1 up"), Err("invalid y".to_owned()));
+        assert_eq!(parse_command("map a.tmx first"), Err("invalid spawn".to_owned()));
+        assert_eq!(parse_command("map a.tmx -1"), Err("invalid spawn".to_owned()));
+        assert_eq!(parse_command("timescale fast"), Err("invalid scale".to_owned()));
+    }
+}
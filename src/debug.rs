@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+
+use fontdue::layout::{Layout, LayoutSettings};
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::BlendMode;
+
+use crate::{
+    enemy::EnemiesState, get_key_pressed, physics::PhysicsState, player::PlayerState,
+    render::RenderingState, InputState,
+};
+
+const FRAME_HISTORY: usize = 120;
+
+pub struct DebugState {
+    pub show: bool,
+    /// Last frame times in seconds, oldest first
+    pub frame_times: VecDeque<f32>,
+    pub frame_timer: std::time::Instant,
+    layout: Layout<Color>,
+}
+
+impl DebugState {
+    pub fn new() -> Self {
+        Self {
+            show: false,
+            frame_times: VecDeque::with_capacity(FRAME_HISTORY),
+            frame_timer: std::time::Instant::now(),
+            layout: Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown),
+        }
+    }
+}
+
+/// Toggles the overlay with F3 and records the frame time
+pub fn update_debug(debug: &mut DebugState, input: &InputState) {
+    if get_key_pressed(Keycode::F3, input) {
+        debug.show = !debug.show;
+    }
+    let now = std::time::Instant::now();
+    let frame_time = now.duration_since(debug.frame_timer).as_secs_f32();
+    debug.frame_timer = now;
+    if debug.frame_times.len() == FRAME_HISTORY {
+        debug.frame_times.pop_front();
+    }
+    debug.frame_times.push_back(frame_time);
+}
+
+fn world_rect(render: &RenderingState, x: i32, y: i32, w: u32, h: u32) -> Rect {
+    Rect::new(x + render.camera.x, y + render.camera.y, w.max(1), h.max(1))
+}
+
+pub fn render_debug(
+    render: &mut RenderingState,
+    debug: &mut DebugState,
+    player: &PlayerState,
+    physics: &PhysicsState,
+    enemies: &EnemiesState,
+) {
+    if !debug.show {
+        return;
+    }
+    // colliders
+    render.canvas.set_draw_color(Color::RGB(255, 0, 0));
+    for col in physics.colliders.iter() {
+        let rect = world_rect(render, col.x, col.y, col.w, col.h);
+        render.canvas.draw_rect(rect).unwrap();
+    }
+    // interactables, brighter when the player stands in them
+    for interactable in physics.interactables.iter() {
+        let col = &interactable.collider;
+        render.canvas.set_draw_color(if interactable.is_in_collider {
+            Color::RGB(0, 255, 255)
+        } else {
+            Color::RGB(0, 100, 255)
+        });
+        let rect = world_rect(render, col.x, col.y, col.w, col.h);
+        render.canvas.draw_rect(rect).unwrap();
+    }
+    // enemy hitboxes
+    render.canvas.set_draw_color(Color::RGB(255, 128, 0));
+    for enemy in enemies.enemies.iter() {
+        let rect = world_rect(
+            render,
+            enemy.x as i32 + enemy.collider.x,
+            enemy.y as i32 + enemy.collider.y,
+            enemy.collider.w,
+            enemy.collider.h,
+        );
+        render.canvas.draw_rect(rect).unwrap();
+    }
+    // player hitbox and velocity
+    let player_rect = world_rect(
+        render,
+        player.x as i32 + player.hitbox.x,
+        player.y as i32 + player.hitbox.y,
+        player.hitbox.w,
+        player.hitbox.h,
+    );
+    render.canvas.set_draw_color(Color::RGB(255, 0, 255));
+    render.canvas.draw_rect(player_rect).unwrap();
+    let center = player_rect.center();
+    let velocity_scale = 0.1;
+    let velocity = Point::new(
+        center.x + ((player.added_velocity.x + player.velocity.x) * velocity_scale) as i32,
+        center.y + ((player.added_velocity.y + player.velocity.y) * velocity_scale) as i32,
+    );
+    render.canvas.set_draw_color(Color::GREEN);
+    render.canvas.draw_line(center, velocity).unwrap();
+
+    // frame time graph, 1px per ms with a line at 60 fps
+    let (canvas_w, canvas_h) = render.canvas.logical_size();
+    let graph_h = 40i32;
+    let graph_x = canvas_w as i32 - FRAME_HISTORY as i32 - 4;
+    let graph_bottom = canvas_h as i32 - 4;
+    render.canvas.set_blend_mode(BlendMode::Blend);
+    render.canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+    render
+        .canvas
+        .fill_rect(Rect::new(graph_x, graph_bottom - graph_h, FRAME_HISTORY as u32, graph_h as u32))
+        .unwrap();
+    render.canvas.set_blend_mode(BlendMode::None);
+    for (i, frame_time) in debug.frame_times.iter().enumerate() {
+        let ms = (frame_time * 1000.0) as i32;
+        render.canvas.set_draw_color(if ms > 17 { Color::RED } else { Color::GREEN });
+        let x = graph_x + i as i32;
+        render
+            .canvas
+            .draw_line(Point::new(x, graph_bottom), Point::new(x, graph_bottom - ms.min(graph_h)))
+            .unwrap();
+    }
+    render.canvas.set_draw_color(Color::YELLOW);
+    render
+        .canvas
+        .draw_line(
+            Point::new(graph_x, graph_bottom - 17),
+            Point::new(graph_x + FRAME_HISTORY as i32, graph_bottom - 17),
+        )
+        .unwrap();
+
+    // text
+    let average = debug.frame_times.iter().sum::<f32>() / debug.frame_times.len().max(1) as f32;
+    let text = format!(
+        "FPS {:.0} ({:.1}ms)\nSTATE {:?}\nGROUNDED {} SLIDING {}\nCOYOTE {:.2}\nJUMP BUF {:.2}\nVEL {:.0} {:.0}",
+        1.0 / average.max(0.0001),
+        average * 1000.0,
+        player.state,
+        player.is_grounded,
+        player.is_sliding,
+        player.coyote_time_counter.max(0.0),
+        player.jump_buffer_counter.max(0.0),
+        player.added_velocity.x + player.velocity.x,
+        player.added_velocity.y + player.velocity.y,
+    );
+    debug.layout.reset(&LayoutSettings {
+        x: 4.0,
+        y: 4.0,
+        ..LayoutSettings::default()
+    });
+    render.fonts.append(&mut debug.layout, &text.to_uppercase(), 8.0, 0, Color::WHITE);
+    render
+        .font_texture
+        .draw_text(&mut render.canvas, render.fonts.as_slice(), debug.layout.glyphs())
+        .unwrap();
+}
@@ -0,0 +1,911 @@
+use std::collections::HashMap;
+
+use fontdue::layout::{Layout, LayoutSettings, LinePosition};
+use json::JsonValue;
+use project_tomato::DIALOGUES_PATH;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    assets::TextureHandle,
+    audio::{play_sound, AudioState, DIALOG_CLOSE_SOUND, DIALOG_PAGE_SOUND},
+    bindings::{Bindings, InputAction},
+    flags::{apply_flag_action, check_conditions, parse_actions, parse_conditions, Action, Condition, FlagStore},
+    fonts::FontRegistry,
+    gamepad::InputDevice,
+    get_action_pressed,
+    i18n::Translator,
+    markup::{apply_text_effects, parse_markup, Markup},
+    player::{PlayerState, PlayerStateMachine},
+    render::{prompt_glyph, RenderingState},
+    speaker::{load_speakers, portrait_size, render_name_tag, render_portrait, PortraitSide, Speaker, SpeakerVoice},
+    InputState,
+};
+
+/// Jump to `node` if every condition passes
+#[derive(Clone, Debug)]
+pub struct Jump {
+    pub conditions: Vec<Condition>,
+    pub node: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct DialogueChoice {
+    /// Translation key
+    pub text: String,
+    /// Choice is hidden unless every condition passes
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+    /// The first passing jump is taken, the dialogue ends when none passes
+    pub next: Vec<Jump>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DialogueNode {
+    /// Id in `speakers.json`
+    pub speaker: Option<String>,
+    /// Translation key
+    pub text: String,
+    pub choices: Vec<DialogueChoice>,
+    /// Run when the node is left
+    pub actions: Vec<Action>,
+    /// Used when there are no choices, the first passing jump is taken, the dialogue ends when none passes
+    pub next: Vec<Jump>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DialogueKind {
+    /// Shown every time
+    Interaction,
+    /// Shown until it is finished once
+    Once,
+}
+
+#[derive(Clone, Debug)]
+pub struct Dialogue {
+    pub id: u32,
+    pub kind: DialogueKind,
+    pub start: Vec<Jump>,
+    pub nodes: HashMap<String, DialogueNode>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DialogResult {
+    Nothing,
+    /// Dialogue id, node id and index in the node choices of the picked choice
+    Choice { dialogue: u32, node: String, index: usize },
+    /// Dialogue id
+    Finished(u32),
+}
+
+/// Lines that fit the dialog box at once, `start` and `end` are glyph indices
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DialogPage {
+    pub start: usize,
+    pub end: usize,
+    /// Top of the first line
+    pub top: f32,
+}
+
+pub struct DialogState {
+    pub color: Color,
+    pub font: usize,
+    layout: Layout<Color>,
+    name_layout: Layout<Color>,
+    /// Chars of the text shown by the typewriter, always at the end of a grapheme
+    current_char: usize,
+    /// Graphemes shown by the typewriter, fractional between frames
+    revealed: f32,
+    /// Char index where every grapheme of the text ends
+    graphemes: Vec<usize>,
+    /// Index of the next pause in `markup`
+    next_pause: usize,
+    /// Seconds left of the current pause
+    pause_left: f32,
+    /// Revealed chars that are not spaces, drives voice blips
+    voiced_chars: usize,
+    /// Pages of the text and choices at the current resolution, updated every frame
+    pages: Vec<DialogPage>,
+    page: usize,
+    /// The typewriter stops here until the player turns the page
+    page_end: usize,
+    pub dialogue: Option<Dialogue>,
+    pub node: Option<String>,
+    /// Translated text of the current node
+    pub text: String,
+    /// Styling of `text` from its inline markup
+    pub markup: Markup,
+    pub speaker: Option<String>,
+    /// Time since the current node was shown, drives portrait animations
+    pub speaker_timer: std::time::Instant,
+    /// Translated choices of the current node
+    pub choices: Vec<String>,
+    /// Index in the node choices of every shown choice
+    choice_indices: Vec<usize>,
+    pub selected: usize,
+    /// Actions that change the world, run by the game loop
+    pub actions: Vec<Action>,
+    finished: bool,
+    pub show: bool,
+    pub dialogues: HashMap<u32, Dialogue>,
+    pub speakers: HashMap<String, Speaker>,
+    /// Portraits and inline icons
+    textures: HashMap<String, TextureHandle>,
+}
+
+impl DialogState {
+    pub fn new() -> Self {
+        Self {
+            color: Color::GREEN,
+            font: 0,
+            current_char: 0,
+            revealed: 0.0,
+            graphemes: vec![],
+            next_pause: 0,
+            pause_left: 0.0,
+            voiced_chars: 0,
+            pages: vec![],
+            page: 0,
+            page_end: usize::MAX,
+            layout: Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown),
+            name_layout: Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown),
+            dialogue: None,
+            node: None,
+            text: "".to_owned(),
+            markup: Markup::default(),
+            speaker: None,
+            speaker_timer: std::time::Instant::now(),
+            choices: vec![],
+            choice_indices: vec![],
+            selected: 0,
+            actions: vec![],
+            finished: false,
+            show: false,
+            dialogues: load_dialogues().unwrap_or_else(|e| {
+                println!("{}", e);
+                HashMap::new()
+            }),
+            speakers: load_speakers(),
+            textures: HashMap::new(),
+        }
+    }
+}
+
+/// A node id or an array of `{"if": conditions, "node": id}`
+fn parse_jumps(value: &JsonValue) -> Option<Vec<Jump>> {
+    match value {
+        JsonValue::Null => Some(vec![]),
+        JsonValue::Array(jumps) => jumps
+            .iter()
+            .map(|jump| {
+                Some(Jump {
+                    conditions: parse_conditions(&jump["if"])?,
+                    node: jump["node"].as_str()?.to_owned(),
+                })
+            })
+            .collect(),
+        node => Some(vec![Jump { conditions: vec![], node: node.as_str()?.to_owned() }]),
+    }
+}
+
+fn parse_node(value: &JsonValue) -> Option<DialogueNode> {
+    Some(DialogueNode {
+        speaker: value["speaker"].as_str().map(|s| s.to_owned()),
+        text: value["text"].as_str()?.to_owned(),
+        choices: value["choices"]
+            .members()
+            .map(|choice| {
+                Some(DialogueChoice {
+                    text: choice["text"].as_str()?.to_owned(),
+                    conditions: parse_conditions(&choice["if"])?,
+                    actions: parse_actions(&choice["actions"])?,
+                    next: parse_jumps(&choice["next"])?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?,
+        actions: parse_actions(&value["actions"])?,
+        next: parse_jumps(&value["next"])?,
+    })
+}
+
+/// Entries either have `start` and `nodes`, or a flat `texts` array shown one after another
+fn parse_dialogue(id: u32, value: &JsonValue) -> Option<Dialogue> {
+    let kind = match value["type"].as_str() {
+        Some("interaction") | None => DialogueKind::Interaction,
+        Some("once") => DialogueKind::Once,
+        Some(_) => return None,
+    };
+    let mut nodes = HashMap::new();
+    if value["texts"].is_array() {
+        let texts: Vec<&str> = value["texts"].members().map(|t| t.as_str()).collect::<Option<_>>()?;
+        for (i, text) in texts.iter().enumerate() {
+            let next = if i + 1 < texts.len() {
+                vec![Jump { conditions: vec![], node: (i + 1).to_string() }]
+            } else {
+                vec![]
+            };
+            nodes.insert(
+                i.to_string(),
+                DialogueNode { speaker: None, text: text.to_string(), choices: vec![], actions: vec![], next },
+            );
+        }
+        let start = vec![Jump { conditions: vec![], node: "0".to_owned() }];
+        return Some(Dialogue { id, kind, start, nodes });
+    }
+    for (node_id, node) in value["nodes"].entries() {
+        nodes.insert(node_id.to_owned(), parse_node(node)?);
+    }
+    let start = parse_jumps(&value["start"])?;
+    Some(Dialogue { id, kind, start, nodes })
+}
+
+/// Reads every dialogue, fails when the file can not be read or parsed so a half-saved file does not replace
+/// the loaded dialogues
+pub fn load_dialogues() -> Result<HashMap<u32, Dialogue>, String> {
+    let data = std::fs::read_to_string(DIALOGUES_PATH)
+        .map_err(|e| format!("Failed to load {}: {}", DIALOGUES_PATH, e))?;
+    let data = json::parse(&data).map_err(|e| format!("Failed to parse {}: {}", DIALOGUES_PATH, e))?;
+    let mut dialogues = HashMap::new();
+    for (id, value) in data.entries() {
+        match id.parse().ok().and_then(|id| parse_dialogue(id, value)) {
+            Some(dialogue) => {
+                dialogues.insert(dialogue.id, dialogue);
+            }
+            None => println!("Invalid dialogue {} in {}", id, DIALOGUES_PATH),
+        }
+    }
+    Ok(dialogues)
+}
+
+const DIALOG_MARGIN: u32 = 5;
+const DIALOG_HEIGHT: u32 = 70;
+
+fn dialog_box(render: &RenderingState) -> Rect {
+    let (canvas_w, canvas_h) = render.canvas.logical_size();
+    Rect::new(
+        DIALOG_MARGIN as i32,
+        (canvas_h - DIALOG_MARGIN - DIALOG_HEIGHT) as i32,
+        canvas_w - 2 * DIALOG_MARGIN,
+        DIALOG_HEIGHT,
+    )
+}
+
+fn current_speaker(dialog: &DialogState) -> Option<&Speaker> {
+    dialog.speaker.as_ref().and_then(|speaker| dialog.speakers.get(speaker))
+}
+
+/// Layout settings for the text, leaving room for the speaker portrait
+fn text_settings(render: &RenderingState, dialog: &DialogState) -> LayoutSettings {
+    let bg = dialog_box(render);
+    let mut x = (bg.x() as u32 + DIALOG_MARGIN) as f32;
+    let mut width = (bg.width() - 2 * DIALOG_MARGIN) as f32;
+    if let Some(speaker) = current_speaker(dialog) {
+        if let Some((portrait_w, _)) = portrait_size(speaker) {
+            width -= (portrait_w + DIALOG_MARGIN) as f32;
+            if speaker.side == PortraitSide::Left {
+                x += (portrait_w + DIALOG_MARGIN) as f32;
+            }
+        }
+    }
+    LayoutSettings {
+        x,
+        y: (bg.y() as u32 + DIALOG_MARGIN) as f32,
+        max_width: Some(width),
+        ..LayoutSettings::default()
+    }
+}
+
+/// Typewriter speed of text without a `[speed]` tag
+const CHARS_PER_SECOND: f32 = 40.0;
+
+/// Char index after every grapheme, so the typewriter never shows half of `ą` written as `a` and a combining mark
+pub fn grapheme_ends(text: &str) -> Vec<usize> {
+    let mut end = 0;
+    text.graphemes(true)
+        .map(|grapheme| {
+            end += grapheme.chars().count();
+            end
+        })
+        .collect()
+}
+
+/// Shows `source` with the typewriter from the start, parsing its markup
+fn set_dialog_text(dialog: &mut DialogState, source: &str) {
+    let (text, markup) = parse_markup(source, Color::WHITE);
+    dialog.graphemes = grapheme_ends(&text);
+    dialog.text = text;
+    dialog.markup = markup;
+    dialog.current_char = 0;
+    dialog.revealed = 0.0;
+    dialog.next_pause = 0;
+    dialog.pause_left = 0.0;
+    dialog.voiced_chars = 0;
+    dialog.pages.clear();
+    dialog.page = 0;
+    dialog.page_end = usize::MAX;
+    dialog.finished = false;
+}
+
+/// Shows the first `count` graphemes
+fn reveal_graphemes(dialog: &mut DialogState, count: usize) {
+    dialog.current_char = if count == 0 { 0 } else { dialog.graphemes[count - 1] };
+}
+
+/// Graphemes that fit before the end of the current page
+fn page_graphemes(dialog: &DialogState) -> usize {
+    dialog.graphemes.partition_point(|end| *end <= dialog.page_end)
+}
+
+/// Reveals graphemes at the speed of the current run, stopping at pauses
+fn advance_typewriter(dialog: &mut DialogState, dt: f32) {
+    if dialog.finished {
+        return;
+    }
+    if dialog.pause_left > 0.0 {
+        dialog.pause_left -= dt;
+        return;
+    }
+    let speed = dialog.markup.run_at(dialog.current_char).map_or(1.0, |run| run.speed);
+    // progress past the page end is dropped so the next page types from its start
+    dialog.revealed = (dialog.revealed + CHARS_PER_SECOND * speed * dt).min(page_graphemes(dialog) as f32);
+    let mut count = dialog.revealed as usize;
+    if let Some(pause) = dialog.markup.pauses.get(dialog.next_pause) {
+        let pause_count = dialog.graphemes.partition_point(|end| *end <= pause.at);
+        if pause_count <= count {
+            count = pause_count;
+            dialog.revealed = count as f32;
+            dialog.next_pause += 1;
+            dialog.pause_left = pause.seconds;
+        }
+    }
+    reveal_graphemes(dialog, count);
+    if count == dialog.graphemes.len() && dialog.pause_left <= 0.0 {
+        dialog.finished = true;
+    }
+}
+
+/// Plays the speaker's voice on the first of every `every` chars revealed since `revealed_from`
+fn play_voice(dialog: &mut DialogState, audio: &mut AudioState, revealed_from: usize) {
+    let revealed = dialog.current_char.saturating_sub(revealed_from);
+    let new_chars = dialog.text.chars().skip(revealed_from).take(revealed).filter(|c| !c.is_whitespace()).count();
+    if new_chars == 0 {
+        return;
+    }
+    let voice = current_speaker(dialog).map_or_else(SpeakerVoice::default, |speaker| speaker.voice.clone());
+    let blips = |chars: usize| (chars + voice.every - 1) / voice.every;
+    let before = dialog.voiced_chars;
+    dialog.voiced_chars += new_chars;
+    if blips(dialog.voiced_chars) != blips(before) {
+        let pitch = audio.vary(voice.pitch, voice.variation);
+        play_sound(audio, &voice.sound, voice.volume, pitch);
+    }
+}
+
+fn page_typed(dialog: &DialogState) -> bool {
+    dialog.revealed as usize >= page_graphemes(dialog)
+}
+
+/// Skips the typewriter to the end of the current page
+fn reveal_page(dialog: &mut DialogState) {
+    let count = page_graphemes(dialog);
+    dialog.revealed = count as f32;
+    reveal_graphemes(dialog, count);
+    while let Some(pause) = dialog.markup.pauses.get(dialog.next_pause) {
+        if pause.at > dialog.current_char {
+            break;
+        }
+        dialog.next_pause += 1;
+    }
+    dialog.pause_left = 0.0;
+    dialog.finished = count == dialog.graphemes.len();
+}
+
+/// Splits laid out lines into pages of at most `height` pixels, choices starting at glyph
+/// `choices_start` are moved to a page of their own rather than split
+fn paginate(lines: &[LinePosition], choices_start: usize, top: f32, height: f32) -> Vec<DialogPage> {
+    let line_top = |line: &LinePosition| line.baseline_y - line.max_ascent;
+    let line_bottom = |line: &LinePosition| line.baseline_y - line.min_descent;
+    let page = |lines: &[LinePosition]| DialogPage {
+        start: lines[0].glyph_start,
+        end: lines[lines.len() - 1].glyph_end + 1,
+        top: line_top(&lines[0]),
+    };
+    if lines.is_empty() {
+        return vec![DialogPage { start: 0, end: 0, top }];
+    }
+    let mut pages = vec![];
+    let mut start = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        if i > start && line_bottom(&lines[i]) - line_top(&lines[start]) > height {
+            let mut split = i;
+            if lines[i].glyph_start >= choices_start {
+                if let Some(first_choice) = (start + 1..i).find(|j| lines[*j].glyph_start >= choices_start) {
+                    split = first_choice;
+                }
+            }
+            pages.push(page(&lines[start..split]));
+            start = split;
+            continue;
+        }
+        i += 1;
+    }
+    pages.push(page(&lines[start..]));
+    pages
+}
+
+
+/// Lays out the whole text with its run colours, wrapped to `settings.max_width`,
+/// glyph indices match char indices of the text
+fn layout_dialog_text(
+    layout: &mut Layout<Color>,
+    fonts: &FontRegistry,
+    text: &str,
+    markup: &Markup,
+    font: usize,
+    font_size: f32,
+    settings: &LayoutSettings,
+) {
+    layout.reset(settings);
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let byte_at = |i: usize| chars.get(i).map_or(text.len(), |(byte, _)| *byte);
+    for run in markup.runs.iter() {
+        let run_text = &text[byte_at(run.start)..byte_at(run.end)];
+        fonts.append(layout, run_text, font_size, font, run.color);
+    }
+}
+
+/// Translation of `key` as markup, keys with no translation are highlighted.
+/// `{enemies}` is the number of enemies left on the map.
+fn translate(lang: &Translator, key: &str, enemy_count: usize) -> String {
+    let translation = lang.format(key, &[("enemies", enemy_count.into())]);
+    if translation.missing {
+        let color = translation.color(Color::WHITE);
+        format!(
+            "[color=#{:02x}{:02x}{:02x}]{}[/color]",
+            color.r,
+            color.g,
+            color.b,
+            key.replace('[', "[[")
+        )
+    } else {
+        translation.text.into_owned()
+    }
+}
+
+fn resolve_jump(jumps: &[Jump], flags: &FlagStore, enemy_count: usize) -> Option<String> {
+    jumps
+        .iter()
+        .find(|jump| check_conditions(flags, &jump.conditions, enemy_count))
+        .map(|jump| jump.node.clone())
+}
+
+/// Shows node `node_id` of the current dialogue, returns false if there is no such node
+fn enter_node(
+    dialog: &mut DialogState,
+    node_id: &str,
+    lang: &Translator,
+    flags: &FlagStore,
+    enemy_count: usize,
+) -> bool {
+    let node = match dialog.dialogue.as_ref().and_then(|d| d.nodes.get(node_id)) {
+        Some(node) => node.clone(),
+        None => {
+            println!("Dialogue node {} not found", node_id);
+            return false;
+        }
+    };
+    dialog.node = Some(node_id.to_owned());
+    set_dialog_text(dialog, &translate(lang, &node.text, enemy_count));
+    dialog.speaker = node.speaker.clone();
+    dialog.speaker_timer = std::time::Instant::now();
+    dialog.color = current_speaker(dialog).map_or(Color::GREEN, |speaker| speaker.color);
+    dialog.choice_indices = (0..node.choices.len())
+        .filter(|i| check_conditions(flags, &node.choices[*i].conditions, enemy_count))
+        .collect();
+    dialog.choices = dialog
+        .choice_indices
+        .iter()
+        .map(|i| parse_markup(&translate(lang, &node.choices[*i].text, enemy_count), Color::WHITE).0)
+        .collect();
+    dialog.selected = 0;
+    true
+}
+
+/// Translates the shown node again after the language changed, the typewriter starts over
+pub fn retranslate_dialog(dialog: &mut DialogState, lang: &Translator, enemy_count: usize) {
+    let node = match (&dialog.dialogue, &dialog.node) {
+        (Some(dialogue), Some(node_id)) => dialogue.nodes.get(node_id).cloned(),
+        _ => None,
+    };
+    let node = match node {
+        Some(node) => node,
+        None => return,
+    };
+    set_dialog_text(dialog, &translate(lang, &node.text, enemy_count));
+    dialog.choices = dialog
+        .choice_indices
+        .iter()
+        .map(|i| parse_markup(&translate(lang, &node.choices[*i].text, enemy_count), Color::WHITE).0)
+        .collect();
+}
+
+/// Flag actions are applied right away so the next jump can depend on them
+fn run_actions(world_actions: &mut Vec<Action>, actions: &[Action], flags: &mut FlagStore) {
+    for action in actions {
+        if !apply_flag_action(flags, action) {
+            world_actions.push(action.clone());
+        }
+    }
+}
+
+fn end_dialog(
+    dialog: &mut DialogState,
+    player: &mut PlayerState,
+    flags: &mut FlagStore,
+    audio: &mut AudioState,
+) -> DialogResult {
+    if dialog.node.is_some() {
+        play_sound(audio, DIALOG_CLOSE_SOUND, 0.5, 1.0);
+    }
+    set_dialog_text(dialog, "");
+    dialog.finished = true;
+    dialog.show = false;
+    player.state = PlayerStateMachine::Idling;
+    player.wants_to_interact = false;
+    dialog.choices.clear();
+    dialog.node = None;
+    match dialog.dialogue.take() {
+        Some(dialogue) => {
+            flags.seen_dialogues.insert(dialogue.id);
+            DialogResult::Finished(dialogue.id)
+        }
+        None => DialogResult::Nothing,
+    }
+}
+
+/// Types the text over time with voice blips, advances with confirm, skips the typewriter with cancel and picks choices
+/// with up and down. Returns the picked choice, or the dialogue that ended when nothing was picked.
+pub fn update_dialog(
+    dialog: &mut DialogState,
+    input: &InputState,
+    player: &mut PlayerState,
+    lang: &Translator,
+    flags: &mut FlagStore,
+    audio: &mut AudioState,
+    enemy_count: usize,
+    dt: f32,
+) -> DialogResult {
+    dialog.show = true;
+    let wants_to_continue = get_action_pressed(InputAction::Confirm, input);
+    let wants_to_skip = get_action_pressed(InputAction::Cancel, input);
+
+    if dialog.node.is_none() {
+        let start = match dialog.dialogue.as_ref() {
+            Some(dialogue) => resolve_jump(&dialogue.start, flags, enemy_count),
+            None => return end_dialog(dialog, player, flags, audio),
+        };
+        match start {
+            Some(start) if enter_node(dialog, &start, lang, flags, enemy_count) => {}
+            _ => return end_dialog(dialog, player, flags, audio),
+        }
+    }
+    let revealed_from = dialog.current_char;
+    if wants_to_skip {
+        reveal_page(dialog);
+    }
+    advance_typewriter(dialog, dt);
+    if !wants_to_skip {
+        play_voice(dialog, audio, revealed_from);
+    }
+    if dialog.page + 1 < dialog.pages.len() {
+        // confirm turns the page once it is typed
+        if wants_to_continue && page_typed(dialog) {
+            dialog.page += 1;
+            dialog.page_end = dialog.pages[dialog.page].end;
+            play_sound(audio, DIALOG_PAGE_SOUND, 0.5, 1.0);
+        }
+        return DialogResult::Nothing;
+    }
+    if !dialog.finished {
+        return DialogResult::Nothing;
+    }
+
+    if !dialog.choices.is_empty() {
+        if get_action_pressed(InputAction::MoveUp, input) {
+            dialog.selected = (dialog.selected + dialog.choices.len() - 1) % dialog.choices.len();
+        }
+        if get_action_pressed(InputAction::MoveDown, input) {
+            dialog.selected = (dialog.selected + 1) % dialog.choices.len();
+        }
+    }
+    if !wants_to_continue {
+        return DialogResult::Nothing;
+    }
+
+    let (dialogue, node_id) = match (&dialog.dialogue, &dialog.node) {
+        (Some(dialogue), Some(node_id)) => (dialogue, node_id),
+        _ => return end_dialog(dialog, player, flags, audio),
+    };
+    let node = &dialogue.nodes[node_id];
+    run_actions(&mut dialog.actions, &node.actions, flags);
+    let (next, result) = if dialog.choices.is_empty() {
+        (resolve_jump(&node.next, flags, enemy_count), DialogResult::Nothing)
+    } else {
+        let index = dialog.choice_indices[dialog.selected];
+        let choice = &node.choices[index];
+        run_actions(&mut dialog.actions, &choice.actions, flags);
+        let result = DialogResult::Choice { dialogue: dialogue.id, node: node_id.clone(), index };
+        (resolve_jump(&choice.next, flags, enemy_count), result)
+    };
+    match next {
+        Some(next) if enter_node(dialog, &next, lang, flags, enemy_count) => {
+            play_sound(audio, DIALOG_PAGE_SOUND, 0.5, 1.0);
+            result
+        }
+        _ => {
+            let finished = end_dialog(dialog, player, flags, audio);
+            // a picked choice matters more than the dialogue ending
+            if result == DialogResult::Nothing { finished } else { result }
+        }
+    }
+}
+
+/// Dialogues of type `once` are skipped after they were finished
+pub fn set_dialog_from_id(id: u32, dialog: &mut DialogState, flags: &FlagStore) {
+    dialog.node = None;
+    dialog.dialogue = dialog.dialogues.get(&id).cloned();
+    match &dialog.dialogue {
+        Some(dialogue) if dialogue.kind == DialogueKind::Once && flags.seen_dialogues.contains(&id) => {
+            dialog.dialogue = None;
+        }
+        Some(_) => {}
+        None => println!("Dialogue {} not found in {}", id, DIALOGUES_PATH),
+    }
+}
+
+pub fn render_dialog(
+    render: &mut RenderingState,
+    dialog: &mut DialogState,
+    lang: &Translator,
+    bindings: &Bindings,
+    device: InputDevice,
+) {
+    if !dialog.show {return}
+
+    let margin = DIALOG_MARGIN;
+    let bg = dialog_box(render);
+    render.canvas.set_draw_color(Color::BLACK);
+    render.canvas.fill_rect(bg).unwrap();
+    render.canvas.set_draw_color(dialog.color);
+    render.canvas.draw_rect(bg).unwrap();
+
+    if let Some(speaker) = current_speaker(dialog).cloned() {
+        if let Some((portrait_w, _)) = portrait_size(&speaker) {
+            let x = match speaker.side {
+                PortraitSide::Left => bg.x() + margin as i32,
+                PortraitSide::Right => bg.right() - (margin + portrait_w) as i32,
+            };
+            let time = dialog.speaker_timer.elapsed().as_secs_f32();
+            render_portrait(render, &speaker, &mut dialog.textures, !dialog.finished, time, x, bg.y() + margin as i32);
+        }
+        let name = lang.t(&speaker.name);
+        render_name_tag(render, &mut dialog.name_layout, &name.text, name.color(speaker.color), speaker.side, bg);
+    }
+
+    let settings = text_settings(render, dialog);
+    let font_size = 8.0;
+    layout_dialog_text(&mut dialog.layout, &render.fonts, &dialog.text, &dialog.markup, dialog.font, font_size, &settings);
+    // choices are laid out with the text so the pages leave room for them
+    for (i, choice) in dialog.choices.iter().enumerate() {
+        let (prefix, color) = if i == dialog.selected { ("> ", Color::YELLOW) } else { ("  ", Color::WHITE) };
+        let line = format!("\n{}{}", prefix, choice);
+        render.fonts.append(&mut dialog.layout, &line, font_size, dialog.font, color);
+    }
+    let lines = dialog.layout.lines().map_or(&[][..], |lines| lines.as_slice());
+    let height = (bg.bottom() - margin as i32) as f32 - settings.y;
+    dialog.pages = paginate(lines, dialog.text.chars().count(), settings.y, height);
+    dialog.page = dialog.page.min(dialog.pages.len() - 1);
+    let page = dialog.pages[dialog.page];
+    dialog.page_end = page.end;
+
+    let time = dialog.speaker_timer.elapsed().as_secs_f32();
+    let mut glyphs = dialog.layout.glyphs().clone();
+    apply_text_effects(&mut glyphs, &dialog.markup, time);
+    let offset = page.top - settings.y;
+    for glyph in glyphs.iter_mut() {
+        glyph.y -= offset;
+    }
+    // the whole text is laid out so words do not jump to the next line while typed
+    let shown_end = if dialog.finished { page.end } else { dialog.current_char.clamp(page.start, page.end) };
+    render.font_texture.draw_text(&mut render.canvas, render.fonts.as_slice(), &glyphs[page.start..shown_end]).unwrap();
+
+    for icon in dialog.markup.icons.iter().filter(|icon| (page.start..shown_end).contains(&icon.at)) {
+        let glyph = &glyphs[icon.at];
+        let line = lines.iter().find(|line| line.glyph_start <= icon.at && icon.at <= line.glyph_end);
+        let y = line.map_or(glyph.y, |line| line.baseline_y - line.max_ascent - offset);
+        let handle = if let Some(handle) = render.assets.acquire_cached(&mut dialog.textures, &icon.texture) { handle } else { continue };
+        if let Some(txt) = render.assets.texture(&render.texture_creator, handle) {
+            let query = txt.query();
+            // scaled to the font size, keeping the aspect ratio
+            let h = font_size as u32;
+            let w = query.width * h / query.height.max(1);
+            render.canvas.copy(txt, None, Rect::new(glyph.x as i32, y as i32, w, h)).unwrap();
+        }
+    }
+
+    if dialog.page + 1 < dialog.pages.len() && page_typed(dialog) {
+        // bobbing arrow or confirm button in the corner tells there is more to read
+        let bob = ((time * 4.0).sin() * 1.5) as i32;
+        let dst = Rect::new(bg.right() - (margin + 8) as i32, bg.bottom() - (margin + 8) as i32 + bob, 8, 8);
+        let glyph = prompt_glyph(render, bindings, device, InputAction::Confirm);
+        if let Some(txt) = glyph.and_then(|handle| render.assets.texture(&render.texture_creator, handle)) {
+            txt.set_color_mod(dialog.color.r, dialog.color.g, dialog.color.b);
+            render.canvas.copy(txt, None, dst).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use project_tomato::TRANSLATIONS_DIR;
+
+    fn polish_texts() -> Vec<String> {
+        let path = format!("{}pl.json", TRANSLATIONS_DIR);
+        let data = json::parse(&std::fs::read_to_string(path).unwrap()).unwrap();
+        data.entries().filter_map(|(_, text)| text.as_str().map(|t| t.to_owned())).collect()
+    }
+
+    #[test]
+    fn graphemes_keep_combining_marks() {
+        assert_eq!(grapheme_ends("żółw"), vec![1, 2, 3, 4]);
+        // ż and ó written with combining marks
+        assert_eq!(grapheme_ends("z\u{307}o\u{301}lw"), vec![2, 4, 5, 6]);
+        assert!(grapheme_ends("").is_empty());
+    }
+
+    #[test]
+    fn typewriter_reveals_whole_graphemes() {
+        let mut dialog = DialogState::new();
+        for text in polish_texts().iter().chain(["Zaz\u{307}o\u{301}ł\u{328}c gęślą jaźń".to_owned()].iter()) {
+            set_dialog_text(&mut dialog, text);
+            let char_count = dialog.text.chars().count();
+            let mut frames = 0;
+            while !dialog.finished {
+                advance_typewriter(&mut dialog, 1.0 / 60.0);
+                assert!(dialog.current_char == 0 || dialog.graphemes.contains(&dialog.current_char));
+                let shown: String = dialog.text.chars().take(dialog.current_char).collect();
+                assert!(dialog.text.starts_with(&shown));
+                frames += 1;
+                assert!(frames < 10_000, "typewriter never finished {:?}", text);
+            }
+            assert_eq!(dialog.current_char, char_count);
+        }
+    }
+
+    #[test]
+    fn typewriter_is_time_based() {
+        let mut dialog = DialogState::new();
+        set_dialog_text(&mut dialog, "Dawno temu jeden pomidor stoczył się z tego wzgórza.");
+        advance_typewriter(&mut dialog, 0.5);
+        assert_eq!(dialog.current_char, (CHARS_PER_SECOND * 0.5) as usize);
+
+        // one long frame or many short ones reveal the same text
+        let mut slow = DialogState::new();
+        set_dialog_text(&mut slow, &dialog.text.clone());
+        for _ in 0..8 {
+            advance_typewriter(&mut slow, 0.0625);
+        }
+        assert_eq!(slow.current_char, dialog.current_char);
+    }
+
+    #[test]
+    fn typewriter_stops_at_pauses() {
+        let mut dialog = DialogState::new();
+        set_dialog_text(&mut dialog, "Żółw[pause=1] śpi");
+        advance_typewriter(&mut dialog, 1.0);
+        assert_eq!(dialog.current_char, 4);
+        advance_typewriter(&mut dialog, 0.5);
+        assert_eq!(dialog.current_char, 4);
+        advance_typewriter(&mut dialog, 0.5);
+        advance_typewriter(&mut dialog, 1.0);
+        assert!(dialog.finished);
+        assert_eq!(dialog.current_char, "Żółw śpi".chars().count());
+    }
+
+    #[test]
+    fn skipping_reveals_everything() {
+        let mut dialog = DialogState::new();
+        set_dialog_text(&mut dialog, "Dzień dobry[pause=2], podróżniku!");
+        reveal_page(&mut dialog);
+        advance_typewriter(&mut dialog, 0.1);
+        assert!(dialog.finished);
+        assert_eq!(dialog.current_char, dialog.text.chars().count());
+    }
+
+    /// kongtext with the fallback font for Polish letters it lacks
+    fn fonts() -> FontRegistry {
+        let font = |bytes: &[u8]| fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default()).unwrap();
+        FontRegistry::new(
+            vec![
+                ("kongtext".to_owned(), font(include_bytes!("../res/kongtext.ttf"))),
+                ("mono".to_owned(), font(&std::fs::read("res/fonts/DejaVuSansMono.ttf").unwrap())),
+            ],
+            Some("mono"),
+        )
+    }
+
+    #[test]
+    fn wrapped_layout_has_a_glyph_per_char() {
+        let fonts = fonts();
+        let mut layout = Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown);
+        let settings = LayoutSettings { max_width: Some(120.0), ..LayoutSettings::default() };
+        for text in polish_texts() {
+            let (text, markup) = parse_markup(&text, Color::WHITE);
+            layout_dialog_text(&mut layout, &fonts, &text, &markup, 0, 8.0, &settings);
+            assert_eq!(layout.glyphs().len(), text.chars().count());
+            let ends = grapheme_ends(&text);
+            for line in layout.lines().into_iter().flatten() {
+                assert!(line.glyph_start == 0 || ends.contains(&line.glyph_start), "{:?} wrapped inside a grapheme", text);
+            }
+        }
+    }
+
+    #[test]
+    fn long_text_is_split_into_pages() {
+        let fonts = fonts();
+        let mut layout = Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown);
+        let settings = LayoutSettings { max_width: Some(150.0), ..LayoutSettings::default() };
+        let text = polish_texts().join(" ");
+        let (text, markup) = parse_markup(&text, Color::WHITE);
+        layout_dialog_text(&mut layout, &fonts, &text, &markup, 0, 8.0, &settings);
+        let char_count = text.chars().count();
+        fonts.append(&mut layout, "\n> Tak\n  Nie", 8.0, 0, Color::YELLOW);
+        let height = 40.0;
+        let lines = layout.lines().unwrap();
+        let pages = paginate(lines, char_count, 0.0, height);
+
+        assert!(pages.len() > 2);
+        assert_eq!(pages[0].start, 0);
+        assert_eq!(pages.last().unwrap().end, layout.glyphs().len());
+        for pair in pages.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        for page in pages.iter() {
+            let bottom = lines
+                .iter()
+                .filter(|line| line.glyph_start >= page.start && line.glyph_end < page.end)
+                .map(|line| line.baseline_y - line.min_descent)
+                .fold(page.top, f32::max);
+            assert!(bottom - page.top <= height);
+        }
+        // both choices stay together on the last page
+        let last = pages.last().unwrap();
+        assert!(last.start <= char_count + 1);
+    }
+
+    #[test]
+    fn typewriter_waits_at_the_end_of_a_page() {
+        let mut dialog = DialogState::new();
+        set_dialog_text(&mut dialog, "Pierwsza strona. Druga strona.");
+        dialog.pages = vec![
+            DialogPage { start: 0, end: 17, top: 0.0 },
+            DialogPage { start: 17, end: 30, top: 10.0 },
+        ];
+        dialog.page_end = 17;
+        advance_typewriter(&mut dialog, 10.0);
+        assert_eq!(dialog.current_char, 17);
+        assert!(page_typed(&dialog));
+        assert!(!dialog.finished);
+
+        // time spent waiting does not carry over to the next page
+        dialog.page = 1;
+        dialog.page_end = 30;
+        advance_typewriter(&mut dialog, 1.0 / CHARS_PER_SECOND);
+        assert_eq!(dialog.current_char, 18);
+        reveal_page(&mut dialog);
+        assert!(dialog.finished);
+    }
+}
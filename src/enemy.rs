@@ -1,8 +1,7 @@
 use glam::Vec2;
-use sdl2::image::LoadTexture;
-use tiled::PropertyValue;
+use project_tomato::properties::{DIR, TEXTURE};
 
-use crate::{physics::Collider, render::{RenderingState, TilemapState, Animation, AnimationFrame}};
+use crate::{assets::TextureHandle, map_error::{int_property, required, string_property, MapLoadError, MapLoadErrorKind}, physics::Collider, render::{acquire_map_texture, RenderingState, TilemapState, Animation, AnimationFrame}};
 
 #[derive(Clone)]
 pub struct EnemiesState{
@@ -22,45 +21,54 @@ pub struct Enemy{
     pub velocity: Vec2,
     pub collider: Collider,
     pub dir: i32,
-    pub texture_path: String,
+    pub texture: TextureHandle,
     pub animation: Animation,
 }
 
-pub fn load_tilemap_to_enemies(enemies: &mut EnemiesState, tile_state: &TilemapState, render: &mut RenderingState) {
-    for tileset in tile_state.tilesets().to_owned() {
-        let image = tileset.image.as_ref().unwrap();
-        let path = image.source.as_os_str().to_str().unwrap();
-        let txt = render.texture_creator.load_texture(path).unwrap();
-        let name = tileset.name.clone();
-        render.textures.insert(name, txt);
+/// Enemy type names, as used by the console, and their textures
+pub const ENEMY_TYPES: [(&str, &str); 1] = [("gumba", "res/tomato_gumba.png")];
+
+/// Returns None for textures without animations
+pub fn create_enemy(txt: &str, texture: TextureHandle, x: f32, y: f32, w: u32, h: u32, dir: i32) -> Option<Enemy> {
+    let col = Collider { y:0, x:0, w, h };
+    // HARD CODED ANIMATIONS
+    let animation_time = 0.5;
+    if txt == "res/tomato_gumba.png" {
+        let mut animation = Animation::new(animation_time);
+        animation.frames.push(AnimationFrame{x: 0, y: 0, w: 16, h:16});
+        animation.frames.push(AnimationFrame{x: 16, y: 0, w: 16, h:16});
+        return Some(Enemy{velocity: Vec2::ZERO,x, y, width: w, height: h, collider: col, dir, texture, animation: animation});
     }
+    None
+}
+
+pub fn spawn_enemy(enemies: &mut EnemiesState, render: &mut RenderingState, kind: &str, x: f32, y: f32) -> Result<(), String> {
+    let txt = ENEMY_TYPES.iter().find(|(name, _)| *name == kind).map(|(_, txt)| *txt).ok_or(format!("unknown enemy type: {}", kind))?;
+    let texture = acquire_map_texture(render, txt).ok_or(format!("missing texture: {}", txt))?;
+    enemies.enemies.push(create_enemy(txt, texture, x, y, 16, 16, -1).unwrap());
+    Ok(())
+}
+
+pub fn load_tilemap_to_enemies(enemies: &mut EnemiesState, tile_state: &TilemapState, render: &mut RenderingState) -> Result<(), MapLoadError> {
     let mut enemies_vec: Vec<Enemy> = vec![];
     for layer in tile_state.layers() {
         if layer.name == "Enemies" {
             match layer.layer_type() {
                 tiled::LayerType::ObjectLayer(obj_layer) => {
                     for obj in obj_layer.objects() {
+                        let at_object = |e: MapLoadError| e.in_object(&layer.name, obj.id());
                         match obj.shape {
                             tiled::ObjectShape::Rect { width, height } => {
                                 let x = obj.x as i32;
                                 let y = obj.y as i32;
                                 let w = width as u32;
                                 let h = height as u32;
-                                let col = Collider { y:0, x:0, w, h };
-                                let dir = if let PropertyValue::IntValue(dir) = obj.properties.get("dir").unwrap() {dir} else {panic!()};
-                                let txt = if let PropertyValue::StringValue(txt) = obj.properties.get("texture").unwrap() {txt} else {panic!()};
-                                if !render.textures.contains_key(txt){
-                                    let texture = render.texture_creator.load_texture(txt).unwrap();
-                                    render.textures.insert(txt.to_owned(),texture);
-                                }
-                                // HARD CODED ANIMATIONS
-                                let animation_time = 0.5;
-                                if txt == "res/tomato_gumba.png" {
-                                    let mut animation = Animation::new(animation_time);
-                                    animation.frames.push(AnimationFrame{x: 0, y: 0, w: 16, h:16});
-                                    animation.frames.push(AnimationFrame{x: 16, y: 0, w: 16, h:16});
-                                    let enemy = Enemy{velocity: Vec2::ZERO,x: x as f32, y: y as f32, width: w, height: h, collider: col, dir: *dir, texture_path: txt.to_owned(), animation: animation};
-                                    enemies_vec.push(enemy);
+                                let dir = required(int_property(&obj.properties, DIR), DIR).map_err(at_object)?;
+                                let txt = required(string_property(&obj.properties, TEXTURE), TEXTURE).map_err(at_object)?;
+                                let texture = if let Some(texture) = acquire_map_texture(render, txt) {texture} else {continue};
+                                match create_enemy(txt, texture, x as f32, y as f32, w, h, dir) {
+                                    Some(enemy) => enemies_vec.push(enemy),
+                                    None => return Err(at_object(MapLoadError::new(MapLoadErrorKind::InvalidValue(format!("no enemy uses texture {}", txt))).with_property(TEXTURE.name))),
                                 }
                             }
                             _ => {}
@@ -72,4 +80,5 @@ pub fn load_tilemap_to_enemies(enemies: &mut EnemiesState, tile_state: &TilemapS
         }
     }
     enemies.enemies.append(&mut enemies_vec);
+    Ok(())
 }
@@ -0,0 +1,327 @@
+use std::collections::{HashMap, HashSet};
+
+use json::JsonValue;
+
+use crate::{
+    physics::PhysicsState,
+    transition::{start_transition, TransitionKind, TransitionState, DEFAULT_TRANSITION_TIME},
+};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    /// Dialogue with this id was finished before
+    Seen(u32),
+    Flag(String),
+    /// Item name and the minimum count
+    Item(String, u32),
+    MinEnemies(usize),
+    MaxEnemies(usize),
+    /// Dialogue id, node id and the choice index last picked there
+    Picked(u32, String, usize),
+    Not(Box<Condition>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Action {
+    SetFlag(String),
+    ClearFlag(String),
+    /// Item name and count
+    GiveItem(String, u32),
+    /// Map path and spawn number
+    ChangeMap(String, u32),
+    /// Removes colliders with a matching `door` property on the current map
+    OpenDoor(String),
+}
+
+/// Global game progress, shared by every map and dialogue
+#[derive(Default, Debug)]
+pub struct FlagStore {
+    pub flags: HashSet<String>,
+    pub items: HashMap<String, u32>,
+    pub seen_dialogues: HashSet<u32>,
+    /// Choice index last picked in each node, by dialogue id and node id
+    pub picked_choices: HashMap<(u32, String), usize>,
+    /// Map path and door name
+    pub opened_doors: HashSet<(String, String)>,
+}
+
+impl FlagStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// `{"seen": 0}`, `{"flag": "name"}`, `{"item": "name", "count": 2}`, `{"min enemies": 1}`
+/// `{"max enemies": 0}` or `{"picked": 1, "dialogue": 0, "node": "ask"}`, any of them negated with `"not": true`
+fn parse_condition(value: &JsonValue) -> Option<Condition> {
+    let condition = if let Some(id) = value["seen"].as_u32() {
+        Condition::Seen(id)
+    } else if let Some(flag) = value["flag"].as_str() {
+        Condition::Flag(flag.to_owned())
+    } else if let Some(item) = value["item"].as_str() {
+        Condition::Item(item.to_owned(), value["count"].as_u32().unwrap_or(1))
+    } else if let Some(count) = value["min enemies"].as_usize() {
+        Condition::MinEnemies(count)
+    } else if let Some(count) = value["max enemies"].as_usize() {
+        Condition::MaxEnemies(count)
+    } else if let Some(index) = value["picked"].as_usize() {
+        Condition::Picked(value["dialogue"].as_u32()?, value["node"].as_str()?.to_owned(), index)
+    } else {
+        return None;
+    };
+    if value["not"].as_bool() == Some(true) {
+        Some(Condition::Not(Box::new(condition)))
+    } else {
+        Some(condition)
+    }
+}
+
+/// A single condition or an array of conditions that all have to pass, missing means no conditions
+pub fn parse_conditions(value: &JsonValue) -> Option<Vec<Condition>> {
+    match value {
+        JsonValue::Null => Some(vec![]),
+        JsonValue::Array(conditions) => conditions.iter().map(parse_condition).collect(),
+        condition => Some(vec![parse_condition(condition)?]),
+    }
+}
+
+/// `{"set flag": "name"}`, `{"clear flag": "name"}`, `{"give item": "name", "count": 1}`,
+/// `{"change map": "res/map.tmx", "spawn place": 0}` or `{"open door": "name"}`
+fn parse_action(value: &JsonValue) -> Option<Action> {
+    if let Some(flag) = value["set flag"].as_str() {
+        Some(Action::SetFlag(flag.to_owned()))
+    } else if let Some(flag) = value["clear flag"].as_str() {
+        Some(Action::ClearFlag(flag.to_owned()))
+    } else if let Some(item) = value["give item"].as_str() {
+        Some(Action::GiveItem(item.to_owned(), value["count"].as_u32().unwrap_or(1)))
+    } else if let Some(path) = value["change map"].as_str() {
+        Some(Action::ChangeMap(path.to_owned(), value["spawn place"].as_u32().unwrap_or(0)))
+    } else if let Some(door) = value["open door"].as_str() {
+        Some(Action::OpenDoor(door.to_owned()))
+    } else {
+        None
+    }
+}
+
+pub fn parse_actions(value: &JsonValue) -> Option<Vec<Action>> {
+    value.members().map(parse_action).collect()
+}
+
+pub fn check_condition(flags: &FlagStore, condition: &Condition, enemy_count: usize) -> bool {
+    match condition {
+        Condition::Seen(id) => flags.seen_dialogues.contains(id),
+        Condition::Flag(flag) => flags.flags.contains(flag),
+        Condition::Item(item, count) => flags.items.get(item).copied().unwrap_or(0) >= *count,
+        Condition::MinEnemies(count) => enemy_count >= *count,
+        Condition::MaxEnemies(count) => enemy_count <= *count,
+        Condition::Picked(dialogue, node, index) => {
+            flags.picked_choices.get(&(*dialogue, node.clone())) == Some(index)
+        }
+        Condition::Not(condition) => !check_condition(flags, condition, enemy_count),
+    }
+}
+
+pub fn check_conditions(flags: &FlagStore, conditions: &[Condition], enemy_count: usize) -> bool {
+    conditions
+        .iter()
+        .all(|condition| check_condition(flags, condition, enemy_count))
+}
+
+/// Applies actions that only touch the flag store, returns false for the ones that change the world
+pub fn apply_flag_action(flags: &mut FlagStore, action: &Action) -> bool {
+    match action {
+        Action::SetFlag(flag) => {
+            flags.flags.insert(flag.clone());
+        }
+        Action::ClearFlag(flag) => {
+            flags.flags.remove(flag);
+        }
+        Action::GiveItem(item, count) => {
+            *flags.items.entry(item.clone()).or_insert(0) += count;
+        }
+        Action::ChangeMap(..) | Action::OpenDoor(_) => return false,
+    }
+    true
+}
+
+pub fn open_door(physics: &mut PhysicsState, name: &str) {
+    let (opened, closed): (Vec<_>, Vec<_>) =
+        std::mem::take(&mut physics.doors).into_iter().partition(|door| door.name == name);
+    let mut removed: Vec<usize> = opened.iter().map(|door| door.collider).collect();
+    removed.sort_unstable();
+    for index in removed.iter().rev() {
+        physics.colliders.remove(*index);
+    }
+    // colliders after the removed ones moved down
+    physics.doors = closed
+        .into_iter()
+        .map(|mut door| {
+            door.collider -= removed.iter().filter(|index| **index < door.collider).count();
+            door
+        })
+        .collect();
+}
+
+/// Opens doors of a freshly loaded map that were opened before
+pub fn open_doors(flags: &FlagStore, physics: &mut PhysicsState, map: &str) {
+    for (door_map, door) in flags.opened_doors.iter() {
+        if door_map == map {
+            open_door(physics, door);
+        }
+    }
+}
+
+/// `map` is the path of the current map, opened doors are remembered for it
+pub fn run_action(
+    action: Action,
+    flags: &mut FlagStore,
+    physics: &mut PhysicsState,
+    transition: &mut TransitionState,
+    map: &str,
+) {
+    if apply_flag_action(flags, &action) {
+        return;
+    }
+    match action {
+        Action::ChangeMap(path, spawn) => start_transition(
+            transition,
+            TransitionKind::Fade,
+            DEFAULT_TRANSITION_TIME,
+            path,
+            spawn,
+        ),
+        Action::OpenDoor(door) => {
+            open_door(physics, &door);
+            flags.opened_doors.insert((map.to_owned(), door));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::{Collider, Door};
+
+    #[test]
+    fn conditions_are_parsed() {
+        assert_eq!(parse_conditions(&json::Null), Some(vec![]));
+        assert_eq!(parse_conditions(&json::object! {"seen": 3}), Some(vec![Condition::Seen(3)]));
+        let conditions = json::array![
+            {"flag": "met guard"},
+            {"item": "key"},
+            {"item": "coin", "count": 5},
+            {"min enemies": 1},
+            {"max enemies": 0, "not": true},
+            {"picked": 1, "dialogue": 2, "node": "ask"},
+        ];
+        assert_eq!(
+            parse_conditions(&conditions),
+            Some(vec![
+                Condition::Flag("met guard".to_owned()),
+                Condition::Item("key".to_owned(), 1),
+                Condition::Item("coin".to_owned(), 5),
+                Condition::MinEnemies(1),
+                Condition::Not(Box::new(Condition::MaxEnemies(0))),
+                Condition::Picked(2, "ask".to_owned(), 1),
+            ])
+        );
+        assert_eq!(parse_conditions(&json::object! {"picked": 1, "node": "ask"}), None);
+        assert_eq!(parse_conditions(&json::object! {"flag": 1}), None);
+        assert_eq!(parse_conditions(&json::array![{"seen": 0}, {"unknown": 0}]), None);
+    }
+
+    #[test]
+    fn actions_are_parsed() {
+        let actions = json::array![
+            {"set flag": "a"},
+            {"clear flag": "b"},
+            {"give item": "coin", "count": 2},
+            {"give item": "key"},
+            {"change map": "res/map2.tmx", "spawn place": 1},
+            {"change map": "res/map.tmx"},
+            {"open door": "gate"},
+        ];
+        assert_eq!(
+            parse_actions(&actions),
+            Some(vec![
+                Action::SetFlag("a".to_owned()),
+                Action::ClearFlag("b".to_owned()),
+                Action::GiveItem("coin".to_owned(), 2),
+                Action::GiveItem("key".to_owned(), 1),
+                Action::ChangeMap("res/map2.tmx".to_owned(), 1),
+                Action::ChangeMap("res/map.tmx".to_owned(), 0),
+                Action::OpenDoor("gate".to_owned()),
+            ])
+        );
+        assert_eq!(parse_actions(&json::Null), Some(vec![]));
+        assert_eq!(parse_actions(&json::array![{"set flag": "a"}, {"open": "gate"}]), None);
+    }
+
+    #[test]
+    fn conditions_are_checked() {
+        let mut flags = FlagStore::new();
+        let conditions = [
+            Condition::Flag("met guard".to_owned()),
+            Condition::Item("coin".to_owned(), 2),
+            Condition::Not(Box::new(Condition::Seen(4))),
+            Condition::MaxEnemies(1),
+        ];
+        assert!(!check_conditions(&flags, &conditions, 0));
+        assert!(apply_flag_action(&mut flags, &Action::SetFlag("met guard".to_owned())));
+        assert!(apply_flag_action(&mut flags, &Action::GiveItem("coin".to_owned(), 1)));
+        assert!(!check_conditions(&flags, &conditions, 0));
+        assert!(apply_flag_action(&mut flags, &Action::GiveItem("coin".to_owned(), 1)));
+        assert!(check_conditions(&flags, &conditions, 1));
+        assert!(!check_conditions(&flags, &conditions, 2));
+        flags.seen_dialogues.insert(4);
+        assert!(!check_conditions(&flags, &conditions, 0));
+
+        let picked = [Condition::Picked(2, "ask".to_owned(), 1)];
+        assert!(!check_conditions(&flags, &picked, 0));
+        flags.picked_choices.insert((2, "ask".to_owned()), 0);
+        assert!(!check_conditions(&flags, &picked, 0));
+        flags.picked_choices.insert((2, "ask".to_owned()), 1);
+        assert!(check_conditions(&flags, &picked, 0));
+        assert!(!apply_flag_action(&mut flags, &Action::OpenDoor("gate".to_owned())));
+    }
+
+    #[test]
+    fn doors_are_opened_on_their_map() {
+        let collider = |x| Collider { x, y: 0, w: 16, h: 16 };
+        let mut physics = PhysicsState::default();
+        // the gate and the wall share a rect, only the gate goes away
+        physics.colliders = vec![collider(0), collider(16), collider(32), collider(16), collider(48)];
+        physics.doors = vec![
+            Door { name: "gate".to_owned(), collider: 1 },
+            Door { name: "cellar".to_owned(), collider: 2 },
+            Door { name: "gate".to_owned(), collider: 4 },
+        ];
+        let mut flags = FlagStore::new();
+        let mut transition = TransitionState::new();
+        run_action(
+            Action::OpenDoor("gate".to_owned()),
+            &mut flags,
+            &mut physics,
+            &mut transition,
+            "res/map.tmx",
+        );
+        assert!(physics.colliders == vec![collider(0), collider(32), collider(16)]);
+        assert_eq!(physics.doors.len(), 1);
+        assert_eq!(physics.doors[0].collider, 1);
+        assert!(flags.opened_doors.contains(&("res/map.tmx".to_owned(), "gate".to_owned())));
+
+        open_door(&mut physics, "cellar");
+        assert!(physics.colliders == vec![collider(0), collider(16)]);
+        assert!(physics.doors.is_empty());
+
+        // a door with the same name on another map stays closed
+        let mut physics = PhysicsState::default();
+        physics.colliders = vec![collider(0)];
+        physics.doors = vec![Door { name: "gate".to_owned(), collider: 0 }];
+        open_doors(&flags, &mut physics, "res/map2.tmx");
+        assert_eq!(physics.colliders.len(), 1);
+        open_doors(&flags, &mut physics, "res/map.tmx");
+        assert!(physics.colliders.is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use fontdue::layout::{Layout, TextStyle};
+use fontdue::Font;
+use project_tomato::{DEFAULT_FONT, FONTS_PATH};
+use sdl2::pixels::Color;
+
+/// Fonts by name, in the order they are registered so maps can still use indices.
+/// Characters a font lacks are drawn with the fallback font.
+pub struct FontRegistry {
+    fonts: Vec<Font>,
+    names: HashMap<String, usize>,
+    fallback: Option<usize>,
+}
+
+impl FontRegistry {
+    /// The default font and the fonts listed in `res/fonts.json`:
+    /// `{"fonts": {"name": "res/path.ttf"}, "fallback": "name"}`
+    pub fn load() -> Self {
+        let default = include_bytes!("../res/kongtext.ttf") as &[u8];
+        let default = Font::from_bytes(default, fontdue::FontSettings::default()).unwrap();
+        let mut registry = Self::new(vec![(DEFAULT_FONT.to_owned(), default)], None);
+
+        let data = match std::fs::read_to_string(FONTS_PATH) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Failed to read {}: {}", FONTS_PATH, e);
+                return registry;
+            }
+        };
+        let data = match json::parse(&data) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Invalid {}: {}", FONTS_PATH, e);
+                return registry;
+            }
+        };
+        for (name, path) in data["fonts"].entries() {
+            let path = match path.as_str() {
+                Some(path) => path,
+                None => {
+                    println!("Font {} in {} should be a path", name, FONTS_PATH);
+                    continue;
+                }
+            };
+            let font = std::fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| Font::from_bytes(bytes, fontdue::FontSettings::default()).map_err(|e| e.to_owned()));
+            match font {
+                Ok(font) => registry.add(name, font),
+                Err(e) => println!("Failed to load font {}: {}", path, e),
+            }
+        }
+        if let Some(fallback) = data["fallback"].as_str() {
+            registry.fallback = registry.index(fallback);
+            if registry.fallback.is_none() {
+                println!("Fallback font {} is not in {}", fallback, FONTS_PATH);
+            }
+        }
+        registry
+    }
+
+    pub fn new(fonts: Vec<(String, Font)>, fallback: Option<&str>) -> Self {
+        let mut registry = Self {
+            fonts: vec![],
+            names: HashMap::new(),
+            fallback: None,
+        };
+        for (name, font) in fonts {
+            registry.add(&name, font);
+        }
+        registry.fallback = fallback.and_then(|name| registry.index(name));
+        registry
+    }
+
+    fn add(&mut self, name: &str, font: Font) {
+        self.names.insert(name.to_owned(), self.fonts.len());
+        self.fonts.push(font);
+    }
+
+    pub fn as_slice(&self) -> &[Font] {
+        &self.fonts
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    /// `font`, or the fallback font when only the fallback has a glyph for `c`
+    pub fn font_for(&self, font: usize, c: char) -> usize {
+        match self.fallback {
+            // whitespace stays in the font of the text so line heights do not change
+            Some(fallback)
+                if !c.is_whitespace()
+                    && self.fonts[font].lookup_glyph_index(c) == 0
+                    && self.fonts[fallback].lookup_glyph_index(c) != 0 =>
+            {
+                fallback
+            }
+            _ => font,
+        }
+    }
+
+    /// Appends `text` to `layout`, split into runs of the font that has each character.
+    /// Still one glyph per char.
+    pub fn append(&self, layout: &mut Layout<Color>, text: &str, px: f32, font: usize, color: Color) {
+        let mut run_start = 0;
+        let mut run_font = None;
+        for (i, c) in text.char_indices() {
+            let char_font = self.font_for(font, c);
+            if run_font != Some(char_font) {
+                if let Some(run_font) = run_font {
+                    layout.append(&self.fonts, &TextStyle::with_user_data(&text[run_start..i], px, run_font, color));
+                }
+                run_start = i;
+                run_font = Some(char_font);
+            }
+        }
+        if let Some(run_font) = run_font {
+            layout.append(&self.fonts, &TextStyle::with_user_data(&text[run_start..], px, run_font, color));
+        }
+    }
+
+    /// Width of `text` on a single line
+    pub fn text_width(&self, text: &str, px: f32, font: usize) -> f32 {
+        text.chars()
+            .map(|c| self.fonts[self.font_for(font, c)].metrics(c, px).advance_width)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> FontRegistry {
+        let kongtext = include_bytes!("../res/kongtext.ttf") as &[u8];
+        let mono = std::fs::read("res/fonts/DejaVuSansMono.ttf").unwrap();
+        FontRegistry::new(
+            vec![
+                ("kongtext".to_owned(), Font::from_bytes(kongtext, fontdue::FontSettings::default()).unwrap()),
+                ("mono".to_owned(), Font::from_bytes(mono, fontdue::FontSettings::default()).unwrap()),
+            ],
+            Some("mono"),
+        )
+    }
+
+    #[test]
+    fn fonts_are_found_by_name() {
+        let fonts = registry();
+        assert_eq!(fonts.index("kongtext"), Some(0));
+        assert_eq!(fonts.index("mono"), Some(1));
+        assert_eq!(fonts.index("comic sans"), None);
+    }
+
+    #[test]
+    fn missing_glyphs_use_the_fallback_font() {
+        let fonts = registry();
+        assert_eq!(fonts.font_for(0, 'a'), 0);
+        assert_eq!(fonts.font_for(0, 'ó'), 0);
+        assert_eq!(fonts.font_for(0, 'ż'), 1);
+        assert_eq!(fonts.font_for(0, ' '), 0);
+        // no font has it, the font of the text draws its missing glyph
+        assert_eq!(fonts.font_for(0, '\u{10FFFD}'), 0);
+    }
+
+    #[test]
+    fn text_is_split_into_runs_of_one_font() {
+        let fonts = registry();
+        let mut layout = Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown);
+        let text = "Zażółć gęślą jaźń\nok";
+        fonts.append(&mut layout, text, 8.0, 0, Color::WHITE);
+        let glyphs = layout.glyphs();
+        assert_eq!(glyphs.len(), text.chars().count());
+        let used: String = glyphs.iter().filter(|glyph| glyph.font_index == 1).map(|glyph| glyph.parent).collect();
+        assert_eq!(used, "żłćęśąźń");
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::GameControllerSubsystem;
+
+use crate::bindings::InputAction;
+
+/// Stick tilt ignored around the centre, worn sticks never rest at exactly zero
+pub const STICK_DEAD_ZONE: f32 = 0.25;
+/// Tilt past which the stick counts as pressing a direction, used to move through menus
+pub const STICK_PRESS_THRESHOLD: f32 = 0.5;
+
+/// Button prompts, actions bound to other buttons fall back to the arrow glyph
+pub const BUTTON_GLYPHS: &[(Button, &str)] = &[
+    (Button::A, "res/glyphs/button_a.png"),
+    (Button::B, "res/glyphs/button_b.png"),
+    (Button::X, "res/glyphs/button_x.png"),
+    (Button::Y, "res/glyphs/button_y.png"),
+    (Button::DPadUp, "res/glyphs/dpad_up.png"),
+    (Button::DPadDown, "res/glyphs/dpad_down.png"),
+    (Button::DPadLeft, "res/glyphs/dpad_left.png"),
+    (Button::DPadRight, "res/glyphs/dpad_right.png"),
+];
+
+/// Device the player used last, prompts show its keys or buttons
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputDevice {
+    Keyboard,
+    Gamepad,
+}
+
+/// Every connected controller acts as the same gamepad
+pub struct GamepadState {
+    subsystem: GameControllerSubsystem,
+    /// By joystick instance id
+    controllers: HashMap<u32, GameController>,
+    pub button_pressed_state: HashMap<Button, bool>,
+    pub button_state: HashMap<Button, bool>,
+    /// Raw left stick from -1.0 to 1.0, y points down
+    left_stick: (f32, f32),
+    /// Directions the stick is pushed in, and the ones pushed this frame
+    stick_actions: Vec<InputAction>,
+    pub stick_pressed: Vec<InputAction>,
+}
+
+impl GamepadState {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Self {
+            subsystem,
+            controllers: HashMap::new(),
+            button_pressed_state: HashMap::new(),
+            button_state: HashMap::new(),
+            left_stick: (0.0, 0.0),
+            stick_actions: vec![],
+            stick_pressed: vec![],
+        }
+    }
+}
+
+/// Opens a controller plugged in while the game runs, SDL also reports the ones connected at startup this way
+pub fn connect_gamepad(gamepad: &mut GamepadState, joystick_index: u32) {
+    match gamepad.subsystem.open(joystick_index) {
+        Ok(controller) => {
+            println!("Gamepad connected: {}", controller.name());
+            gamepad.controllers.insert(controller.instance_id(), controller);
+        }
+        Err(e) => println!("Failed to open gamepad {}: {}", joystick_index, e),
+    }
+}
+
+pub fn disconnect_gamepad(gamepad: &mut GamepadState, instance_id: u32) {
+    if let Some(controller) = gamepad.controllers.remove(&instance_id) {
+        println!("Gamepad disconnected: {}", controller.name());
+    }
+    // held buttons never get their release event
+    gamepad.button_state.clear();
+    gamepad.left_stick = (0.0, 0.0);
+}
+
+/// Stores stick motion, returns whether the stick left the dead zone
+pub fn move_stick(gamepad: &mut GamepadState, axis: Axis, value: i16) -> bool {
+    let value = (value as f32 / i16::MAX as f32).clamp(-1.0, 1.0);
+    match axis {
+        Axis::LeftX => gamepad.left_stick.0 = value,
+        Axis::LeftY => gamepad.left_stick.1 = value,
+        _ => return false,
+    }
+    gamepad_stick(gamepad) != (0.0, 0.0)
+}
+
+/// Left stick with the dead zone removed
+pub fn gamepad_stick(gamepad: &GamepadState) -> (f32, f32) {
+    apply_dead_zone(gamepad.left_stick, STICK_DEAD_ZONE)
+}
+
+/// Radial dead zone, the tilt past it is scaled back to 0.0..=1.0 so slow walking is possible
+pub fn apply_dead_zone((x, y): (f32, f32), dead_zone: f32) -> (f32, f32) {
+    let length = (x * x + y * y).sqrt();
+    if length <= dead_zone {
+        return (0.0, 0.0);
+    }
+    let scale = ((length - dead_zone) / (1.0 - dead_zone)).min(1.0) / length;
+    (x * scale, y * scale)
+}
+
+/// Directions `stick` is pushed in far enough to press them
+pub fn stick_actions(stick: (f32, f32)) -> Vec<InputAction> {
+    let mut actions = vec![];
+    if stick.0 <= -STICK_PRESS_THRESHOLD {
+        actions.push(InputAction::MoveLeft);
+    }
+    if stick.0 >= STICK_PRESS_THRESHOLD {
+        actions.push(InputAction::MoveRight);
+    }
+    if stick.1 <= -STICK_PRESS_THRESHOLD {
+        actions.push(InputAction::MoveUp);
+    }
+    if stick.1 >= STICK_PRESS_THRESHOLD {
+        actions.push(InputAction::MoveDown);
+    }
+    actions
+}
+
+/// Call once per frame after the events, finds the directions the stick was pushed in
+pub fn update_stick(gamepad: &mut GamepadState) {
+    let actions = stick_actions(gamepad_stick(gamepad));
+    gamepad.stick_pressed = actions
+        .iter()
+        .filter(|action| !gamepad.stick_actions.contains(action))
+        .copied()
+        .collect();
+    gamepad.stick_actions = actions;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dead_zone_is_removed() {
+        assert_eq!(apply_dead_zone((0.2, -0.1), 0.25), (0.0, 0.0));
+        assert_eq!(apply_dead_zone((1.0, 0.0), 0.25), (1.0, 0.0));
+        let (x, y) = apply_dead_zone((0.0, -0.625), 0.25);
+        assert_eq!(x, 0.0);
+        assert!((y + 0.5).abs() < 1e-6, "{}", y);
+        // diagonals past the edge do not get faster
+        let (x, y) = apply_dead_zone((1.0, 1.0), 0.25);
+        assert!(((x * x + y * y).sqrt() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn stick_presses_directions_past_the_threshold() {
+        assert_eq!(stick_actions((0.0, 0.0)), vec![]);
+        assert_eq!(stick_actions((-0.4, 0.3)), vec![]);
+        assert_eq!(stick_actions((-0.7, 0.0)), vec![InputAction::MoveLeft]);
+        assert_eq!(stick_actions((0.6, -0.6)), vec![InputAction::MoveRight, InputAction::MoveUp]);
+    }
+}
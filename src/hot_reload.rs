@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use project_tomato::{DIALOGUES_PATH, SPEAKERS_PATH, TRANSLATIONS_DIR};
+
+pub const SFX_DIR: &str = "res/sfx/";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchedKind {
+    Map,
+    Dialogues,
+    Translations,
+    Sounds,
+}
+
+#[derive(Default, Debug)]
+pub struct Changes {
+    pub map: bool,
+    pub dialogues: bool,
+    pub translations: bool,
+    pub sounds: bool,
+}
+
+pub struct HotReloadState {
+    pub files: HashMap<PathBuf, (WatchedKind, Option<SystemTime>)>,
+    pub map_path: String,
+    pub last_poll: Instant,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl HotReloadState {
+    pub fn new() -> Self {
+        let mut state = Self {
+            files: HashMap::new(),
+            map_path: "".to_owned(),
+            last_poll: Instant::now(),
+        };
+        state.watch(PathBuf::from(DIALOGUES_PATH), WatchedKind::Dialogues);
+        state.watch(PathBuf::from(SPEAKERS_PATH), WatchedKind::Dialogues);
+        if let Ok(entries) = std::fs::read_dir(TRANSLATIONS_DIR) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().map_or(false, |e| e == "json") {
+                    state.watch(path, WatchedKind::Translations);
+                }
+            }
+        }
+        if let Ok(entries) = std::fs::read_dir(SFX_DIR) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().map_or(false, |e| e == "wav") {
+                    state.watch(path, WatchedKind::Sounds);
+                }
+            }
+        }
+        state
+    }
+
+    fn watch(&mut self, path: PathBuf, kind: WatchedKind) {
+        let time = modified(&path);
+        self.files.insert(path, (kind, time));
+    }
+}
+
+/// Tilesets referenced by `<tileset source="...">` in a TMX file, relative to the map
+fn map_tilesets(map_path: &Path) -> Vec<PathBuf> {
+    let dir = map_path.parent().unwrap_or(Path::new(""));
+    let data = std::fs::read_to_string(map_path).unwrap_or_default();
+    data.lines()
+        .filter(|line| line.trim_start().starts_with("<tileset"))
+        .filter_map(|line| {
+            let start = line.find("source=\"")? + "source=\"".len();
+            let end = start + line[start..].find('"')?;
+            Some(dir.join(&line[start..end]))
+        })
+        .collect()
+}
+
+/// Replaces the watched map files with the current map and its tilesets
+fn watch_map(state: &mut HotReloadState, map_path: &str) {
+    state.files.retain(|_, (kind, _)| *kind != WatchedKind::Map);
+    state.map_path = map_path.to_owned();
+    let map_path = PathBuf::from(map_path);
+    for tileset in map_tilesets(&map_path) {
+        state.watch(tileset, WatchedKind::Map);
+    }
+    state.watch(map_path, WatchedKind::Map);
+}
+
+/// Checks modification times every `POLL_INTERVAL`, starts watching `current_map` when it changes
+pub fn poll_changes(state: &mut HotReloadState, current_map: &str) -> Changes {
+    let mut changes = Changes::default();
+    if state.map_path != current_map {
+        watch_map(state, current_map);
+    }
+    if state.last_poll.elapsed() < POLL_INTERVAL {
+        return changes;
+    }
+    state.last_poll = Instant::now();
+    for (path, (kind, last_modified)) in state.files.iter_mut() {
+        let time = modified(path);
+        if time != *last_modified {
+            *last_modified = time;
+            println!("Reloading {}", path.display());
+            match kind {
+                WatchedKind::Map => changes.map = true,
+                WatchedKind::Dialogues => changes.dialogues = true,
+                WatchedKind::Translations => changes.translations = true,
+                WatchedKind::Sounds => changes.sounds = true,
+            }
+        }
+    }
+    if changes.map {
+        // tilesets might have been added or removed
+        let map_path = state.map_path.clone();
+        watch_map(state, &map_path);
+    }
+    changes
+}
@@ -0,0 +1,41 @@
+use fontdue::layout::{HorizontalAlign, Layout, LayoutSettings};
+use sdl2::pixels::Color;
+
+use crate::{flags::FlagStore, i18n::Translator, render::RenderingState};
+
+/// Item given for every stomped enemy
+pub const TOMATO_ITEM: &str = "tomato";
+
+pub struct HudState {
+    layout: Layout<Color>,
+}
+
+impl HudState {
+    pub fn new() -> Self {
+        Self {
+            layout: Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown),
+        }
+    }
+}
+
+/// Tomato counter in the top right corner, hidden until the first one is collected
+pub fn render_hud(render: &mut RenderingState, hud: &mut HudState, flags: &FlagStore, lang: &Translator) {
+    let tomatoes = flags.items.get(TOMATO_ITEM).copied().unwrap_or(0);
+    if tomatoes == 0 {
+        return;
+    }
+    let text = lang.format("hud-tomatoes", &[("count", tomatoes.into())]);
+    let (canvas_w, _) = render.canvas.logical_size();
+    hud.layout.reset(&LayoutSettings {
+        x: 4.0,
+        y: 4.0,
+        max_width: Some(canvas_w as f32 - 8.0),
+        horizontal_align: HorizontalAlign::Right,
+        ..LayoutSettings::default()
+    });
+    render.fonts.append(&mut hud.layout, &text.text, 8.0, 0, text.color(Color::WHITE));
+    render
+        .font_texture
+        .draw_text(&mut render.canvas, render.fonts.as_slice(), hud.layout.glyphs())
+        .unwrap();
+}
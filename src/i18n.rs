@@ -0,0 +1,267 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+
+use json::JsonValue;
+use project_tomato::{plural_category, TRANSLATIONS_DIR};
+use sdl2::pixels::Color;
+
+pub const LOCALES: &[&str] = &["en", "pl"];
+/// Used for keys the current locale is missing
+pub const FALLBACK_LOCALE: &str = "en";
+/// Keys missing in every locale are drawn as they are in this colour
+pub const MISSING_TRANSLATION_COLOR: Color = Color::MAGENTA;
+
+/// Translations of every locale, looked up in the current locale, then `FALLBACK_LOCALE`, then
+/// the key itself.
+///
+/// A translation is a string with `{name}` placeholders, or an object of plural forms picked by
+/// the `count` argument (or the argument named by `"plural"`):
+/// `{"=0": "no tomatoes", "one": "{count} tomato", "other": "{count} tomatoes"}`.
+/// Exact `=N` forms win over the locale's plural categories, `other` is used when a category
+/// is missing.
+pub struct Translator {
+    current: String,
+    locales: HashMap<String, JsonValue>,
+    /// Locale and key of every lookup that missed, each is logged once
+    missing: RefCell<BTreeSet<(String, String)>>,
+}
+
+/// Value of a `{name}` placeholder
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Arg<'a> {
+    Text(&'a str),
+    Number(i64),
+}
+
+impl<'a> From<&'a str> for Arg<'a> {
+    fn from(text: &'a str) -> Self {
+        Arg::Text(text)
+    }
+}
+
+impl From<i64> for Arg<'_> {
+    fn from(number: i64) -> Self {
+        Arg::Number(number)
+    }
+}
+
+impl From<u32> for Arg<'_> {
+    fn from(number: u32) -> Self {
+        Arg::Number(number as i64)
+    }
+}
+
+impl From<usize> for Arg<'_> {
+    fn from(number: usize) -> Self {
+        Arg::Number(number as i64)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Translation<'a> {
+    pub text: Cow<'a, str>,
+    /// No locale has the key, `text` is the key
+    pub missing: bool,
+}
+
+impl Translation<'_> {
+    /// `color`, or the debug colour when the key is missing
+    pub fn color(&self, color: Color) -> Color {
+        if self.missing {
+            MISSING_TRANSLATION_COLOR
+        } else {
+            color
+        }
+    }
+}
+
+impl Translator {
+    /// Reads `<locale>.json` of every locale from `TRANSLATIONS_DIR`
+    pub fn load(current: &str) -> Self {
+        let mut locales = HashMap::new();
+        for locale in LOCALES {
+            let path = format!("{}{}.json", TRANSLATIONS_DIR, locale);
+            let data = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| json::parse(&data).map_err(|e| e.to_string()));
+            let translations = match data {
+                Ok(translations) => translations,
+                Err(e) => {
+                    println!("Failed to load {}: {}", path, e);
+                    JsonValue::new_object()
+                }
+            };
+            locales.insert(locale.to_string(), translations);
+        }
+        Self::from_locales(current, locales)
+    }
+
+    pub fn from_locales(current: &str, locales: HashMap<String, JsonValue>) -> Self {
+        Self {
+            current: current.to_owned(),
+            locales,
+            missing: RefCell::new(BTreeSet::new()),
+        }
+    }
+
+    pub fn set_current_lang(&mut self, code: &str) {
+        self.current = code.to_owned();
+    }
+
+    /// Text of `key` in `locale` with the plural form picked from `args`
+    fn lookup(&self, locale: &str, key: &str, args: &[(&str, Arg)]) -> Option<&str> {
+        let value = &self.locales.get(locale)?[key];
+        let text = value.as_str().or_else(|| {
+            let selector = value["plural"].as_str().unwrap_or("count");
+            let count = args.iter().find_map(|(name, arg)| match arg {
+                Arg::Number(number) if *name == selector => Some(*number),
+                _ => None,
+            })?;
+            value[format!("={}", count).as_str()]
+                .as_str()
+                .or_else(|| value[plural_category(locale, count)].as_str())
+                .or_else(|| value["other"].as_str())
+        });
+        if text.is_none() && self.missing.borrow_mut().insert((locale.to_owned(), key.to_owned())) {
+            println!("Missing translation for \"{}\" in {}", key, locale);
+        }
+        text
+    }
+
+    pub fn t<'a>(&'a self, key: &'a str) -> Translation<'a> {
+        self.format(key, &[])
+    }
+
+    /// Translation of `key` with `{name}` placeholders replaced by `args`
+    pub fn format<'a>(&'a self, key: &'a str, args: &[(&str, Arg)]) -> Translation<'a> {
+        let text = self.lookup(&self.current, key, args).or_else(|| {
+            if self.current == FALLBACK_LOCALE {
+                None
+            } else {
+                self.lookup(FALLBACK_LOCALE, key, args)
+            }
+        });
+        match text {
+            Some(text) => Translation { text: interpolate(text, args), missing: false },
+            None => Translation { text: Cow::Borrowed(key), missing: true },
+        }
+    }
+
+    /// Locale and key of every translation that was looked up and missing so far
+    pub fn missing_keys(&self) -> Vec<(String, String)> {
+        self.missing.borrow().iter().cloned().collect()
+    }
+}
+
+/// Replaces `{name}` with the argument called `name`, unknown placeholders are kept
+fn interpolate<'a>(text: &'a str, args: &[(&str, Arg)]) -> Cow<'a, str> {
+    if !text.contains('{') {
+        return Cow::Borrowed(text);
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        let name = &rest[1..end];
+        match args.iter().find(|(arg_name, _)| *arg_name == name) {
+            Some((_, Arg::Text(value))) => result.push_str(value),
+            Some((_, Arg::Number(value))) => result.push_str(&value.to_string()),
+            None => result.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Cow::Owned(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translator(current: &str) -> Translator {
+        let mut locales = HashMap::new();
+        locales.insert(
+            "en".to_owned(),
+            json::object! {
+                "yes": "Yes",
+                "no": "No",
+                "tomatoes": { "=0": "No tomatoes", "one": "{count} tomato", "other": "{count} tomatoes" },
+                "greeting": "Hi {name}, {missing}",
+            },
+        );
+        locales.insert(
+            "pl".to_owned(),
+            json::object! {
+                "yes": "Tak",
+                "tomatoes": { "one": "{count} pomidor", "few": "{count} pomidory", "many": "{count} pomidorów" },
+                "left": { "plural": "enemies", "one": "Została {enemies}", "other": "Zostało {enemies}" },
+            },
+        );
+        Translator::from_locales(current, locales)
+    }
+
+    #[test]
+    fn missing_keys_fall_back_to_english() {
+        let lang = translator("pl");
+        assert_eq!(lang.t("yes"), Translation { text: "Tak".into(), missing: false });
+        assert_eq!(lang.t("no"), Translation { text: "No".into(), missing: false });
+        assert_eq!(lang.missing_keys(), vec![("pl".to_owned(), "no".to_owned())]);
+    }
+
+    #[test]
+    fn keys_missing_everywhere_are_shown_as_they_are() {
+        let lang = translator("pl");
+        let translation = lang.t("maybe");
+        assert_eq!(translation, Translation { text: "maybe".into(), missing: true });
+        assert_eq!(translation.color(Color::WHITE), MISSING_TRANSLATION_COLOR);
+        lang.t("maybe");
+        assert_eq!(
+            lang.missing_keys(),
+            vec![("en".to_owned(), "maybe".to_owned()), ("pl".to_owned(), "maybe".to_owned())]
+        );
+    }
+
+    #[test]
+    fn unknown_locales_use_english() {
+        let lang = translator("de");
+        assert_eq!(lang.t("no").text, "No");
+    }
+
+    #[test]
+    fn polish_has_three_plural_forms() {
+        let expected = [(1, "one"), (2, "few"), (4, "few"), (5, "many"), (12, "many"), (22, "few"), (25, "many"), (0, "many")];
+        for (n, category) in expected {
+            assert_eq!(plural_category("pl", n), category, "{}", n);
+        }
+        let lang = translator("pl");
+        let tomatoes = |count: u32| lang.format("tomatoes", &[("count", count.into())]).text.into_owned();
+        assert_eq!(tomatoes(1), "1 pomidor");
+        assert_eq!(tomatoes(3), "3 pomidory");
+        assert_eq!(tomatoes(11), "11 pomidorów");
+        assert_eq!(lang.format("left", &[("enemies", 2usize.into())]).text, "Zostało 2");
+    }
+
+    #[test]
+    fn plurals_fall_back_with_english_rules() {
+        let lang = translator("en");
+        let tomatoes = |count: i64| lang.format("tomatoes", &[("count", count.into())]).text.into_owned();
+        assert_eq!(tomatoes(0), "No tomatoes");
+        assert_eq!(tomatoes(1), "1 tomato");
+        assert_eq!(tomatoes(2), "2 tomatoes");
+        assert!(lang.t("tomatoes").missing);
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let lang = translator("en");
+        assert_eq!(lang.format("greeting", &[("name", "Tom".into())]).text, "Hi Tom, {missing}");
+        assert!(matches!(lang.t("yes").text, Cow::Borrowed("Yes")));
+        assert_eq!(interpolate("{a}{b} {", &[("a", 1i64.into()), ("b", "x".into())]), "1x {");
+    }
+}
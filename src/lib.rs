@@ -0,0 +1,169 @@
+//! Paths and rules shared by the game and `tomato-lint`, so the two never disagree.
+
+use tiled::PropertyValue;
+
+pub const DIALOGUES_PATH: &str = "res/dialogues.json";
+pub const SPEAKERS_PATH: &str = "res/speakers.json";
+pub const TRANSLATIONS_DIR: &str = "res/translations/";
+pub const FONTS_PATH: &str = "res/fonts.json";
+/// Built in so there is always something to draw text with, it is font 0
+pub const DEFAULT_FONT: &str = "kongtext";
+
+/// Plural categories `plural_category` picks for whole numbers in each locale
+pub const PLURAL_FORMS: &[(&str, &[&str])] = &[("en", &["one", "other"]), ("pl", &["one", "few", "many"])];
+
+/// CLDR plural category of an integer
+pub fn plural_category(locale: &str, n: i64) -> &'static str {
+    let n = n.abs();
+    match locale {
+        "pl" => {
+            if n == 1 {
+                "one"
+            } else if (2..=4).contains(&(n % 10)) && !(12..=14).contains(&(n % 100)) {
+                "few"
+            } else {
+                "many"
+            }
+        }
+        _ => {
+            if n == 1 {
+                "one"
+            } else {
+                "other"
+            }
+        }
+    }
+}
+
+/// How the game reads a Tiled property
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PropertyType {
+    Int,
+    /// Ints are accepted too
+    Float,
+    String,
+    Color,
+    /// Font name, or its index in the font registry for older maps
+    Font,
+}
+
+impl PropertyType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PropertyType::Int => "int",
+            PropertyType::Float => "float",
+            PropertyType::String => "string",
+            PropertyType::Color => "color",
+            PropertyType::Font => "string or int",
+        }
+    }
+
+    /// Whether the game can read `value` as this type
+    pub fn accepts(&self, value: &PropertyValue) -> bool {
+        matches!(
+            (self, value),
+            (PropertyType::Int, PropertyValue::IntValue(_))
+                | (PropertyType::Float, PropertyValue::FloatValue(_) | PropertyValue::IntValue(_))
+                | (PropertyType::String, PropertyValue::StringValue(_))
+                | (PropertyType::Color, PropertyValue::ColorValue(_))
+                | (PropertyType::Font, PropertyValue::StringValue(_) | PropertyValue::IntValue(_))
+        )
+    }
+}
+
+/// A Tiled property the game reads, the typed property helpers only accept ones of their type
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Property {
+    pub name: &'static str,
+    pub kind: PropertyType,
+}
+
+/// Every property the game reads from maps, `tomato-lint` checks maps against the same lists
+pub mod properties {
+    use super::Property;
+    use super::PropertyType::*;
+
+    pub const SPAWN_PLACE: Property = Property { name: "spawn place", kind: Int };
+    pub const MAP_CHANGE: Property = Property { name: "map change", kind: String };
+    pub const TRANSITION: Property = Property { name: "transition", kind: String };
+    pub const TRANSITION_TIME: Property = Property { name: "transition time", kind: Float };
+    pub const INSPECT_ID: Property = Property { name: "inspect id", kind: Int };
+    pub const DIR: Property = Property { name: "dir", kind: Int };
+    pub const TEXTURE: Property = Property { name: "texture", kind: String };
+    pub const TEXT: Property = Property { name: "text", kind: String };
+    pub const FONT: Property = Property { name: "font", kind: Font };
+    pub const DOOR: Property = Property { name: "door", kind: String };
+    pub const SOUND: Property = Property { name: "sound", kind: String };
+    pub const VOLUME: Property = Property { name: "volume", kind: Float };
+    pub const RADIUS: Property = Property { name: "radius", kind: Float };
+    pub const COLOR: Property = Property { name: "color", kind: Color };
+    pub const FLICKER: Property = Property { name: "flicker", kind: Float };
+
+    pub const MUSIC: Property = Property { name: "music", kind: String };
+    pub const AMBIENT_DARKNESS: Property = Property { name: "ambient darkness", kind: Float };
+    pub const AMBIENT_COLOR: Property = Property { name: "ambient color", kind: Color };
+    pub const PLAYER_LIGHT: Property = Property { name: "player light", kind: Float };
+    pub const PLAYER_LIGHT_COLOR: Property = Property { name: "player light color", kind: Color };
+
+    pub const OBJECT_PROPERTIES: &[Property] = &[
+        SPAWN_PLACE,
+        MAP_CHANGE,
+        TRANSITION,
+        TRANSITION_TIME,
+        INSPECT_ID,
+        DIR,
+        TEXTURE,
+        TEXT,
+        FONT,
+        DOOR,
+        SOUND,
+        VOLUME,
+        RADIUS,
+        COLOR,
+        FLICKER,
+    ];
+    pub const MAP_PROPERTIES: &[Property] = &[MUSIC, AMBIENT_DARKNESS, AMBIENT_COLOR, PLAYER_LIGHT, PLAYER_LIGHT_COLOR];
+    /// Properties an object needs on a given layer
+    pub const REQUIRED_PROPERTIES: &[(&str, &[Property])] = &[
+        ("PlayerSpawners", &[SPAWN_PLACE]),
+        ("Enemies", &[DIR, TEXTURE]),
+        ("TextHints", &[FONT, TEXT]),
+        ("Sounds", &[SOUND]),
+    ];
+}
+
+/// Type of a Tiled property as written in map errors
+pub fn type_name(value: &PropertyValue) -> &'static str {
+    match value {
+        PropertyValue::BoolValue(_) => "bool",
+        PropertyValue::FloatValue(_) => "float",
+        PropertyValue::IntValue(_) => "int",
+        PropertyValue::ColorValue(_) => "color",
+        PropertyValue::StringValue(_) => "string",
+        PropertyValue::FileValue(_) => "file",
+        PropertyValue::ObjectValue(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plural_forms_cover_every_category() {
+        for (locale, forms) in PLURAL_FORMS {
+            for n in 0..200 {
+                assert!(forms.contains(&plural_category(locale, n)), "{} {}", locale, n);
+            }
+        }
+    }
+
+    #[test]
+    fn floats_accept_ints() {
+        assert!(PropertyType::Float.accepts(&PropertyValue::IntValue(3)));
+        assert!(PropertyType::Float.accepts(&PropertyValue::FloatValue(0.5)));
+        assert!(!PropertyType::Int.accepts(&PropertyValue::FloatValue(0.5)));
+        assert!(PropertyType::Font.accepts(&PropertyValue::IntValue(0)));
+        assert!(!PropertyType::Font.accepts(&PropertyValue::BoolValue(true)));
+    }
+}
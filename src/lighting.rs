@@ -0,0 +1,233 @@
+use project_tomato::properties::{AMBIENT_COLOR, AMBIENT_DARKNESS, COLOR, FLICKER, PLAYER_LIGHT, PLAYER_LIGHT_COLOR, RADIUS};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Texture};
+
+use crate::{
+    map_error::{color_property, float_property, MapLoadError},
+    player::PlayerState,
+    render::{RenderingState, TilemapState},
+};
+
+const GLOW_SIZE: u32 = 64;
+
+#[derive(Clone)]
+pub struct Light {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub color: Color,
+    /// How much the light flickers, 0.0 - 1.0
+    pub flicker: f32,
+    phase: f32,
+}
+
+pub struct LightingState {
+    /// Lighting pass only runs on maps with ambient darkness
+    pub enabled: bool,
+    pub ambient: Color,
+    pub lights: Vec<Light>,
+    pub player_light: Option<Light>,
+    pub time: f32,
+    light_map: Option<Texture>,
+    glow: Option<Texture>,
+}
+
+impl LightingState {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            ambient: Color::WHITE,
+            lights: vec![],
+            player_light: None,
+            time: 0.0,
+            light_map: None,
+            glow: None,
+        }
+    }
+}
+
+/// Reads `ambient darkness`, `ambient color`, `player light` and `player light color` map properties
+/// and point lights from the `Lights` layer
+pub fn load_tilemap_to_lights(state: &mut LightingState, tile_state: &TilemapState) -> Result<(), MapLoadError> {
+    state.lights.clear();
+    state.player_light = None;
+
+    let darkness = float_property(&tile_state.properties, AMBIENT_DARKNESS)?
+        .unwrap_or(0.0)
+        .clamp(0.0, 1.0);
+    state.enabled = darkness > 0.0;
+    if !state.enabled {
+        return Ok(());
+    }
+    let ambient = color_property(&tile_state.properties, AMBIENT_COLOR)?.unwrap_or(Color::WHITE);
+    let brightness = 1.0 - darkness;
+    state.ambient = Color::RGB(
+        (ambient.r as f32 * brightness) as u8,
+        (ambient.g as f32 * brightness) as u8,
+        (ambient.b as f32 * brightness) as u8,
+    );
+
+    if let Some(radius) = float_property(&tile_state.properties, PLAYER_LIGHT)? {
+        let color = color_property(&tile_state.properties, PLAYER_LIGHT_COLOR)?.unwrap_or(Color::WHITE);
+        state.player_light = Some(Light { x: 0.0, y: 0.0, radius, color, flicker: 0.0, phase: 0.0 });
+    }
+
+    for layer in tile_state.layers() {
+        if layer.name == "Lights" {
+            match layer.layer_type() {
+                tiled::LayerType::ObjectLayer(obj_layer) => {
+                    for obj in obj_layer.objects() {
+                        let at_object = |e: MapLoadError| e.in_object(&layer.name, obj.id());
+                        // lights are placed at the center of the object
+                        let (x, y, size) = match obj.shape {
+                            tiled::ObjectShape::Rect { width, height }
+                            | tiled::ObjectShape::Ellipse { width, height } => {
+                                (obj.x + width / 2.0, obj.y + height / 2.0, width.max(height))
+                            }
+                            tiled::ObjectShape::Point(..) => (obj.x, obj.y, 0.0),
+                            _ => continue,
+                        };
+                        let radius = float_property(&obj.properties, RADIUS)
+                            .map_err(at_object)?
+                            .unwrap_or(size / 2.0);
+                        let color = color_property(&obj.properties, COLOR)
+                            .map_err(at_object)?
+                            .unwrap_or(Color::WHITE);
+                        let flicker = float_property(&obj.properties, FLICKER)
+                            .map_err(at_object)?
+                            .unwrap_or(0.0)
+                            .clamp(0.0, 1.0);
+                        state.lights.push(Light {
+                            x,
+                            y,
+                            radius,
+                            color,
+                            flicker,
+                            phase: x * 0.37 + y * 0.11,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn update_lighting(state: &mut LightingState, player: &PlayerState, dt: f32) {
+    state.time += dt;
+    if let Some(light) = state.player_light.as_mut() {
+        light.x = player.x + player.width as f32 / 2.0;
+        light.y = player.y + player.height as f32 / 2.0;
+    }
+}
+
+/// Radial gradient, white in the middle and transparent on the edges
+fn create_glow_texture(render: &RenderingState) -> Result<Texture, String> {
+    let mut glow = render
+        .texture_creator
+        .create_texture_static(PixelFormatEnum::RGBA32, GLOW_SIZE, GLOW_SIZE)
+        .map_err(|e| e.to_string())?;
+    let mut pixels = vec![0u8; (GLOW_SIZE * GLOW_SIZE * 4) as usize];
+    let half = GLOW_SIZE as f32 / 2.0;
+    for y in 0..GLOW_SIZE {
+        for x in 0..GLOW_SIZE {
+            let dx = (x as f32 + 0.5 - half) / half;
+            let dy = (y as f32 + 0.5 - half) / half;
+            let falloff = (1.0 - (dx * dx + dy * dy).sqrt()).max(0.0);
+            let value = (falloff * falloff * 255.0) as u8;
+            let i = ((y * GLOW_SIZE + x) * 4) as usize;
+            pixels[i..i + 4].copy_from_slice(&[value, value, value, 255]);
+        }
+    }
+    glow.update(None, &pixels, (GLOW_SIZE * 4) as usize)
+        .map_err(|e| e.to_string())?;
+    glow.set_blend_mode(BlendMode::Add);
+    Ok(glow)
+}
+
+fn flicker(light: &Light, time: f32) -> f32 {
+    if light.flicker == 0.0 {
+        return 1.0;
+    }
+    let noise = (time * 13.0 + light.phase).sin() * (time * 7.3 + light.phase * 2.0).sin();
+    1.0 - light.flicker * (0.5 + 0.5 * noise)
+}
+
+/// Multiplies everything rendered so far by the light map
+pub fn render_lighting(render: &mut RenderingState, state: &mut LightingState) {
+    if !state.enabled {
+        return;
+    }
+    let (canvas_w, canvas_h) = render.canvas.logical_size();
+    let needs_light_map = match &state.light_map {
+        Some(light_map) => {
+            let query = light_map.query();
+            query.width != canvas_w || query.height != canvas_h
+        }
+        None => true,
+    };
+    if needs_light_map {
+        if let Some(old) = state.light_map.take() {
+            unsafe { old.destroy() };
+        }
+        match render
+            .texture_creator
+            .create_texture_target(PixelFormatEnum::RGBA32, canvas_w, canvas_h)
+        {
+            Ok(mut light_map) => {
+                light_map.set_blend_mode(BlendMode::Mod);
+                state.light_map = Some(light_map);
+            }
+            Err(e) => {
+                println!("Lighting disabled, failed to create light map: {}", e);
+                state.enabled = false;
+                return;
+            }
+        }
+    }
+    if state.glow.is_none() {
+        match create_glow_texture(render) {
+            Ok(glow) => state.glow = Some(glow),
+            Err(e) => {
+                println!("Lighting disabled, failed to create glow texture: {}", e);
+                state.enabled = false;
+                return;
+            }
+        }
+    }
+
+    let light_map = state.light_map.as_mut().unwrap();
+    let glow = state.glow.as_mut().unwrap();
+    let camera = render.camera;
+    let ambient = state.ambient;
+    let time = state.time;
+    let lights = state.lights.iter().chain(state.player_light.iter());
+    let result = render.canvas.with_texture_canvas(light_map, |canvas| {
+        canvas.set_draw_color(ambient);
+        canvas.clear();
+        for light in lights {
+            let intensity = flicker(light, time);
+            let radius = light.radius * (0.9 + 0.1 * intensity);
+            glow.set_color_mod(
+                (light.color.r as f32 * intensity) as u8,
+                (light.color.g as f32 * intensity) as u8,
+                (light.color.b as f32 * intensity) as u8,
+            );
+            let dst = Rect::new(
+                (light.x - radius) as i32 + camera.x,
+                (light.y - radius) as i32 + camera.y,
+                (radius * 2.0) as u32,
+                (radius * 2.0) as u32,
+            );
+            canvas.copy(glow, None, dst).unwrap();
+        }
+    });
+    if let Err(e) = result {
+        println!("Lighting disabled, render targets unsupported: {}", e);
+        state.enabled = false;
+        return;
+    }
+    render.canvas.copy(light_map, None, None).unwrap();
+}
@@ -1,17 +1,18 @@
 extern crate sdl2;
 
-use fontdue::layout::Layout;
-use fontdue::layout::LayoutSettings;
-use fontdue::layout::TextStyle;
-use json::JsonValue;
-use r_i18n::I18n;
-use r_i18n::I18nConfig;
 use sdl2::event::Event;
+use sdl2::event::WindowEvent;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
 use sdl2::EventPump;
 use std::collections::HashMap;
 
+mod flags;
+use crate::flags::*;
+mod speaker;
+use crate::speaker::*;
+mod markup;
+mod dialog;
+use crate::dialog::*;
 mod render;
 use crate::render::*;
 mod physics;
@@ -20,192 +21,148 @@ mod enemy;
 use crate::enemy::*;
 mod player;
 use crate::player::*;
+mod particles;
+use crate::particles::*;
+mod transition;
+use crate::transition::*;
+mod settings;
+use crate::settings::*;
+mod menu;
+use crate::menu::*;
+mod lighting;
+use crate::lighting::*;
+mod debug;
+use crate::debug::*;
+mod console;
+use crate::console::*;
+mod hot_reload;
+use crate::hot_reload::*;
+mod assets;
+use crate::assets::*;
+mod audio;
+use crate::audio::*;
+mod map_error;
+use crate::map_error::*;
+mod i18n;
+use crate::i18n::*;
+mod hud;
+use crate::hud::*;
+mod fonts;
+mod bindings;
+use crate::bindings::*;
+mod gamepad;
+use crate::gamepad::*;
 
-pub struct DialogState {
-    color: Color,
-    font: usize,
-    layout: Layout<Color>,
-    current_char: usize,
-    text: String,
-    texts: Vec<String>,
-    finished: bool,
-    show: bool,
-    dialogues: JsonValue,
-}
+/// Frames are limited to 60 per second when vsync is off
+const FRAME_TIME: std::time::Duration = std::time::Duration::from_micros(16_667);
 
-impl DialogState {
-    pub fn new() -> Self {
-        Self {
-            color: Color::GREEN,
-            font: 0,
-            current_char: 0,
-            layout: Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown),
-            text: "".to_owned(),
-            texts: vec![], //vec!["asdf".to_owned(), "dfasdgasdg".to_owned(), "ahrhger".to_owned()],
-            finished: false,
-            show: false,
-            dialogues: json::parse(&std::fs::read_to_string("res/dialogues.json").unwrap())
-                .unwrap(),
+/// Language picked with `--lang <code>` or the `TOMATO_LANG` environment variable
+fn language_override() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--lang" {
+            return args.next();
+        }
+        if let Some(code) = arg.strip_prefix("--lang=") {
+            return Some(code.to_owned());
         }
     }
+    std::env::var("TOMATO_LANG").ok()
 }
 
-pub fn apply_word_wrap_to_dialog(render: &RenderingState, dialog: &mut DialogState) {
-    if dialog.text.is_empty() {
-        return;
-    };
-    let (canvas_w, canvas_h) = render.canvas.logical_size();
-    let margin = 5u32;
-    let height = 70u32;
-    let font_size = 8.0;
-    let settings = LayoutSettings {
-        x: (margin + margin) as f32,
-        y: (canvas_h - margin + margin - height) as f32,
-        max_width: Some((canvas_w - 4 * margin) as f32),
-        ..LayoutSettings::default()
-    };
-    dialog.layout.reset(&settings);
-    dialog.layout.append(
-        render.fonts.as_slice(),
-        &TextStyle::with_user_data(&dialog.text, font_size, dialog.font, Color::WHITE),
-    );
-    let mut new_text = dialog.text.clone();
-    let mut new_line_places: Vec<_> = dialog
-        .layout
-        .lines()
-        .unwrap()
-        .iter()
-        .map(|e| e.glyph_end)
-        .collect();
-    new_line_places.pop();
-    for place in new_line_places.iter() {
-        new_text = replace_nth_char(&new_text, *place, '\n');
-    }
-    dialog.text = new_text;
-    fn replace_nth_char(s: &str, idx: usize, newchar: char) -> String {
-        s.chars()
-            .enumerate()
-            .map(|(i, c)| if i == idx { newchar } else { c })
-            .collect()
-    }
-}
-
-pub fn update_dialog(
+/// Switches the language and translates the text that is already laid out
+fn change_language(
+    code: &str,
+    lang: &mut Translator,
+    render: &mut RenderingState,
     dialog: &mut DialogState,
-    input: &InputState,
-    player: &mut PlayerState,
-    render: &RenderingState,
+    enemy_count: usize,
 ) {
-    dialog.show = true;
-    let wants_to_continue = get_key_pressed(Keycode::Z, input);
-    let wants_to_skip = get_key_pressed(Keycode::X, input);
-
-    if dialog.text.is_empty() && !dialog.texts.is_empty() {
-        dialog.finished = false;
-        dialog.text = dialog.texts.first().unwrap().clone();
-        dialog.texts.remove(0);
-        apply_word_wrap_to_dialog(render, dialog);
-    }
-    if wants_to_skip {
-        dialog.current_char = 999;
-        dialog.finished = true;
-    }
-    if dialog.texts.is_empty() && wants_to_continue && dialog.finished {
-        dialog.finished = true;
-        dialog.show = false;
-        player.state = PlayerStateMachine::Idling;
-        player.wants_to_interact = false;
-        dialog.current_char = 0;
-        dialog.text.clear();
-    } else if wants_to_continue && dialog.finished {
-        dialog.finished = false;
-        dialog.text = dialog.texts.first().unwrap().clone();
-        dialog.texts.remove(0);
-        dialog.current_char = 0;
-        apply_word_wrap_to_dialog(render, dialog);
-    }
-}
-
-pub fn set_dialog_from_id(id: u32, dialog: &mut DialogState, lang: &I18n) {
-    match dialog.dialogues.clone() {
-        JsonValue::Object(whole) => {
-            let objj = whole.get(&id.to_string()).unwrap();
-            match objj {
-                JsonValue::Object(obj) => {
-                    let _dialog_type = obj.get("type").unwrap().as_str().unwrap();
-                    let texts_array = if let JsonValue::Array(texts) = obj.get("texts").unwrap() {
-                        texts
-                    } else {
-                        panic!()
-                    };
-                    let texts: Vec<String> = texts_array
-                        .iter()
-                        .map(|text| {
-                            if let JsonValue::Short(texte) = text {
-                                texte.as_str()
-                            } else {
-                                panic!()
-                            }
-                        })
-                        .map(|v| lang.t(v).as_str().unwrap().to_owned())
-                        .collect();
-
-                    dialog.texts = texts;
-                    return;
-                }
-                _ => {}
-            }
-        }
-        _ => {}
-    }
-    panic!("Failed to parse dialogues.json");
+    lang.set_current_lang(code);
+    retranslate_text_hints(render, lang);
+    retranslate_dialog(dialog, lang, enemy_count);
 }
 
 pub struct InputState {
     pub event_pump: EventPump,
     pub should_quit: bool,
+    pub window_resized: bool,
+    /// Text typed this frame
+    pub text_input: String,
     pub key_pressed_state: HashMap<Keycode, bool>,
     pub key_released_state: HashMap<Keycode, bool>,
     pub key_state: HashMap<Keycode, bool>,
+    pub gamepad: GamepadState,
+    pub last_device: InputDevice,
+    pub bindings: Bindings,
 }
 
 pub fn main() -> Result<(), String> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
+    let controller_subsystem = sdl_context.game_controller()?;
+    let mut settings = Settings::load();
+    let mut audio_state = AudioState::new(&sdl_context);
+    set_volume(&mut audio_state, &settings.audio);
 
     let window = video_subsystem
-        .window("Project tomato", 1280, 720)
+        .window("Project tomato", settings.video.width, settings.video.height)
         .position_centered()
-        .fullscreen_desktop()
+        .resizable()
         .build()
         .map_err(|e| e.to_string())?;
 
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-    canvas
-        .set_logical_size(320, 180)
-        .map_err(|e| e.to_string())?; // set later for resolution (320, 180) for 16:9 and (320, 240) for 4:3 etc
+    let mut canvas_builder = window.into_canvas();
+    if settings.video.vsync {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = canvas_builder.build().map_err(|e| e.to_string())?;
+    apply_video_settings(&mut canvas, &settings.video)?;
     let event_pump = sdl_context.event_pump()?;
+    let text_input = video_subsystem.text_input();
+    text_input.stop();
 
     let mut rendering_state = RenderingState::new(canvas);
     let mut input_state = InputState {
         event_pump,
         should_quit: false,
+        window_resized: false,
+        text_input: "".to_owned(),
         key_pressed_state: HashMap::new(),
         key_released_state: HashMap::new(),
         key_state: HashMap::new(),
+        gamepad: GamepadState::new(controller_subsystem),
+        last_device: InputDevice::Keyboard,
+        bindings: Bindings::load(),
     };
     let mut player_state = PlayerState::new(0.0, 0.0);
     let mut physics_state = PhysicsState::default();
     let mut enemies_state = EnemiesState::new();
     let mut animation_state = AnimationState::new();
     let mut dialog_state = DialogState::new();
+    let mut particles_state = ParticlesState::new();
+    let mut transition_state = TransitionState::new();
+    let mut menu_state = MenuState::new();
+    let mut lighting_state = LightingState::new();
+    let mut debug_state = DebugState::new();
+    let mut console_state = ConsoleState::new();
+    let mut error_screen_state = ErrorScreenState::new();
+    let mut hud_state = HudState::new();
+    let mut flags = FlagStore::new();
 
-    let i18n_config: I18nConfig = I18nConfig {
-        locales: &["en", "pl"],
-        directory: "res/translations/",
-    };
-    let mut lang: I18n = I18n::configure(&i18n_config);
-    lang.set_current_lang("en");
+    if !LOCALES.contains(&settings.language.as_str()) {
+        println!("Unknown language {}, using {}", settings.language, LOCALES[0]);
+        settings.language = LOCALES[0].to_owned();
+    }
+    settings.language_override = language_override().filter(|code| {
+        let known = LOCALES.contains(&code.as_str());
+        if !known {
+            println!("Unknown language {}, using {}", code, settings.language);
+        }
+        known
+    });
+    let mut lang = Translator::load(settings.current_language());
+    let mut hot_reload_state = HotReloadState::new();
 
     let mut loader = tiled::Loader::new();
     let mut start_map = switch_map(
@@ -217,7 +174,11 @@ pub fn main() -> Result<(), String> {
         &mut player_state,
         &mut enemies_state,
         &mut physics_state,
-    );
+        &mut lighting_state,
+        &flags,
+        &mut audio_state,
+    )
+    .map_err(|e| e.to_string())?;
     // -------------------- GAME LOOP -------------------- //
 
     loop {
@@ -227,24 +188,134 @@ pub fn main() -> Result<(), String> {
         let render_enemies_state = enemies_state.clone();
 
         input(&mut input_state);
-        if player_state.state == PlayerStateMachine::Talking {
-            update_dialog(
+        update_debug(&mut debug_state, &input_state);
+        if input_state.window_resized {
+            update_logical_size(&mut rendering_state.canvas, &settings.video)?;
+        }
+        let was_console_open = console_state.open;
+        if let Some(command) = update_console(&mut console_state, &input_state) {
+            if let Some(new_map) = run_console_command(
+                command,
+                &mut console_state,
+                &mut loader,
+                &mut lang,
+                &mut settings,
+                &mut rendering_state,
+                &mut player_state,
+                &mut enemies_state,
+                &mut physics_state,
+                &mut lighting_state,
+                &mut dialog_state,
+                &mut error_screen_state,
+                &flags,
+                &mut audio_state,
+            ) {
+                start_map = new_map;
+                particles_state.particles.clear();
+            }
+        }
+        if console_state.open != was_console_open {
+            if console_state.open {
+                text_input.start();
+            } else {
+                text_input.stop();
+            }
+        }
+        if console_state.open {
+            // Escape and not the pause action, the pause key may be typed in the console
+            if get_key_pressed(Keycode::Escape, &input_state) {
+                console_state.open = false;
+                text_input.stop();
+            }
+        } else if get_action_pressed(InputAction::Pause, &input_state) && menu_state.waiting_for_input.is_none() {
+            toggle_menu(&mut menu_state);
+        }
+        if console_state.open {
+            lock_player_input(&mut player_state);
+        } else if menu_state.open {
+            match update_menu(&mut menu_state, &input_state, &mut settings) {
+                MenuResult::Nothing => {}
+                MenuResult::SettingsChanged => {
+                    apply_video_settings(&mut rendering_state.canvas, &settings.video)?;
+                    rendering_state.vsync = renderer_vsync(&rendering_state.canvas);
+                    settings.save();
+                }
+                MenuResult::VolumeChanged => {
+                    set_volume(&mut audio_state, &settings.audio);
+                    settings.save();
+                }
+                MenuResult::LanguageChanged => {
+                    change_language(
+                        settings.current_language(),
+                        &mut lang,
+                        &mut rendering_state,
+                        &mut dialog_state,
+                        enemies_state.enemies.len(),
+                    );
+                    settings.save();
+                }
+                MenuResult::Rebind(action, binding) => {
+                    input_state.bindings.bind(action, binding);
+                    input_state.bindings.save();
+                }
+                MenuResult::ResetBindings => {
+                    input_state.bindings = Bindings::default();
+                    input_state.bindings.save();
+                }
+                MenuResult::Quit => input_state.should_quit = true,
+            }
+            lock_player_input(&mut player_state);
+        } else if error_screen_state.error.is_some() {
+            update_error_screen(&mut error_screen_state, &input_state);
+            lock_player_input(&mut player_state);
+        } else if is_transitioning(&transition_state) {
+            // input is locked until the new map is uncovered
+            lock_player_input(&mut player_state);
+        } else if player_state.state == PlayerStateMachine::Talking {
+            let result = update_dialog(
                 &mut dialog_state,
                 &input_state,
                 &mut player_state,
-                &rendering_state,
+                &lang,
+                &mut flags,
+                &mut audio_state,
+                enemies_state.enemies.len(),
+                physics_state.dt,
             );
+            if let DialogResult::Choice { dialogue, node, index } = result {
+                flags.picked_choices.insert((dialogue, node), index);
+            }
+            for action in dialog_state.actions.drain(..) {
+                run_action(
+                    action,
+                    &mut flags,
+                    &mut physics_state,
+                    &mut transition_state,
+                    &player_state.current_map,
+                );
+            }
         } else {
             move_player(&mut player_state, &input_state);
         }
-        animate(&mut animation_state, &mut player_state, &mut enemies_state);
         count_dt(&mut physics_state);
-        player_physics(&physics_state, &mut player_state);
-        enemies_physics(&physics_state, &mut enemies_state);
+        if menu_state.open {
+            // game is paused
+            physics_state.dt = 0.0;
+        } else {
+            animate(&mut animation_state, &mut player_state, &mut enemies_state);
+        }
+        player_physics(&physics_state, &mut player_state, &mut particles_state, &mut audio_state);
+        enemies_physics(&physics_state, &mut enemies_state, &mut audio_state);
         player_collision_interactables(&mut physics_state, &mut player_state);
-        player_enemies_hit(&mut player_state, &mut enemies_state);
-
-        //println!("STATE: {:?}", player_state.state);
+        let stomped = player_enemies_hit(&mut player_state, &mut enemies_state, &mut particles_state, &mut audio_state);
+        if stomped > 0 {
+            apply_flag_action(&mut flags, &Action::GiveItem(TOMATO_ITEM.to_owned(), stomped as u32));
+        }
+        set_listener(&mut audio_state, &rendering_state.camera, rendering_state.canvas.logical_size());
+        set_music_ducked(&mut audio_state, dialog_state.show);
+        update_audio(&mut audio_state, physics_state.dt);
+        update_particles(&mut particles_state, physics_state.dt);
+        update_lighting(&mut lighting_state, &render_player_state, physics_state.dt);
 
         render(
             &mut rendering_state,
@@ -253,33 +324,93 @@ pub fn main() -> Result<(), String> {
             &render_player_state,
             &render_enemies_state,
             &render_physics_state,
+            &particles_state,
+            &mut lighting_state,
+            &transition_state,
+            &mut menu_state,
+            &settings,
+            &mut debug_state,
+            &mut console_state,
             &mut dialog_state,
+            &mut error_screen_state,
+            &mut hud_state,
+            &flags,
+            &input_state.bindings,
+            input_state.last_device,
         );
 
-        let interaction_result = player_interact(
-            &mut loader,
-            &lang,
-            &mut rendering_state,
-            &mut player_state,
-            &mut enemies_state,
-            &mut physics_state,
-        );
+        let interaction_result = player_interact(&mut player_state, &mut physics_state);
         match interaction_result {
             InteractionResult::Nothing => {}
-            InteractionResult::ChangeMap(new_map) => start_map = new_map,
+            InteractionResult::ChangeMap(path, spawn_number, transition, time) => {
+                play_sfx(&mut audio_state, Sfx::Interact);
+                start_transition(&mut transition_state, transition, time, path, spawn_number);
+            }
             InteractionResult::Inspect(inspect_id) => {
-                set_dialog_from_id(inspect_id, &mut dialog_state, &lang)
+                play_sfx(&mut audio_state, Sfx::Interact);
+                set_dialog_from_id(inspect_id, &mut dialog_state, &flags)
             }
         }
 
-        if player_state.state == PlayerStateMachine::Dying{
-            start_map = switch_map(&mut loader, player_state.current_map.clone().as_str(), player_state.spawn_point.clone(), &lang, &mut rendering_state, &mut player_state, &mut enemies_state, &mut physics_state);
+        if player_state.state == PlayerStateMachine::Dying && !is_transitioning(&transition_state) {
+            play_sfx(&mut audio_state, Sfx::Death);
+            start_transition(
+                &mut transition_state,
+                TransitionKind::Fade,
+                DEFAULT_TRANSITION_TIME,
+                player_state.current_map.clone(),
+                player_state.spawn_point,
+            );
+        }
+        if let Some((path, spawn_number)) = update_transition(&mut transition_state, physics_state.dt) {
+            match switch_map(&mut loader, &path, spawn_number, &lang, &mut rendering_state, &mut player_state, &mut enemies_state, &mut physics_state, &mut lighting_state, &flags, &mut audio_state) {
+                Ok(map) => start_map = map,
+                Err(e) => show_map_error(&mut error_screen_state, e),
+            }
             player_state.state = PlayerStateMachine::Idling;
+            player_state.velocity = glam::Vec2::ZERO;
+            player_state.added_velocity = glam::Vec2::ZERO;
+            particles_state.particles.clear();
         }
 
-        let _frame_end_time = frame_timer.elapsed();
-        //println!("{}", 1.0/_frame_end_time.as_secs_f64());
-        std::thread::sleep(std::time::Duration::from_millis(16)); // ONLY FOR DEV PURPOSES
+        let changes = poll_changes(&mut hot_reload_state, &player_state.current_map);
+        if changes.translations {
+            lang = Translator::load(settings.current_language());
+            retranslate_text_hints(&mut rendering_state, &lang);
+            retranslate_dialog(&mut dialog_state, &lang, enemies_state.enemies.len());
+        }
+        if changes.dialogues {
+            // a broken file keeps the dialogues that are loaded
+            match load_dialogues() {
+                Ok(dialogues) => dialog_state.dialogues = dialogues,
+                Err(e) => println!("{}", e),
+            }
+            dialog_state.speakers = load_speakers();
+        }
+        if changes.sounds {
+            audio_state.reload_sounds();
+        }
+        if changes.map {
+            let (x, y) = (player_state.x, player_state.y);
+            loader = tiled::Loader::new(); // drop cached tilesets
+            rendering_state.assets.reload_textures();
+            let path = player_state.current_map.clone();
+            match switch_map(&mut loader, &path, player_state.spawn_point, &lang, &mut rendering_state, &mut player_state, &mut enemies_state, &mut physics_state, &mut lighting_state, &flags, &mut audio_state) {
+                Ok(map) => {
+                    start_map = map;
+                    player_state.x = x;
+                    player_state.y = y;
+                }
+                Err(e) => show_map_error(&mut error_screen_state, e),
+            }
+        }
+
+        // presenting already waits with vsync, the limiter would add a second delay
+        if !rendering_state.vsync {
+            if let Some(rest) = FRAME_TIME.checked_sub(frame_timer.elapsed()) {
+                std::thread::sleep(rest);
+            }
+        }
         if input_state.should_quit {
             break;
         };
@@ -288,45 +419,133 @@ pub fn main() -> Result<(), String> {
     Ok(())
 }
 
+/// Everything `switch_map` replaces, kept to restore the previous map when the new one fails to load
+struct PreviousMap {
+    textures: Vec<TextureHandle>,
+    tileset_textures: std::collections::HashMap<std::path::PathBuf, TextureHandle>,
+    text_hints: Vec<TextHint>,
+    colliders: Vec<Collider>,
+    interactables: Vec<Interactable>,
+    doors: Vec<Door>,
+    enemies: Vec<Enemy>,
+    lighting: (bool, sdl2::pixels::Color, Vec<Light>, Option<Light>),
+    player_position: (f32, f32),
+    /// Resized to the new map before anything else is loaded
+    camera: sdl2::rect::Rect,
+}
+
+impl PreviousMap {
+    fn take(
+        render: &mut RenderingState,
+        player: &PlayerState,
+        enemies: &mut EnemiesState,
+        physics: &mut PhysicsState,
+        lighting: &mut LightingState,
+    ) -> Self {
+        Self {
+            textures: std::mem::take(&mut render.map_textures),
+            tileset_textures: std::mem::take(&mut render.tileset_textures),
+            text_hints: std::mem::take(&mut render.text_hints),
+            colliders: std::mem::take(&mut physics.colliders),
+            interactables: std::mem::take(&mut physics.interactables),
+            doors: std::mem::take(&mut physics.doors),
+            enemies: std::mem::take(&mut enemies.enemies),
+            lighting: (
+                lighting.enabled,
+                lighting.ambient,
+                std::mem::take(&mut lighting.lights),
+                lighting.player_light.take(),
+            ),
+            player_position: (player.x, player.y),
+            camera: render.camera,
+        }
+    }
+
+    fn restore(
+        self,
+        render: &mut RenderingState,
+        player: &mut PlayerState,
+        enemies: &mut EnemiesState,
+        physics: &mut PhysicsState,
+        lighting: &mut LightingState,
+    ) {
+        let failed_textures = std::mem::replace(&mut render.map_textures, self.textures);
+        release_map_textures(render, failed_textures);
+        render.tileset_textures = self.tileset_textures;
+        render.text_hints = self.text_hints;
+        physics.colliders = self.colliders;
+        physics.interactables = self.interactables;
+        physics.doors = self.doors;
+        enemies.enemies = self.enemies;
+        (lighting.enabled, lighting.ambient, lighting.lights, lighting.player_light) = self.lighting;
+        (player.x, player.y) = self.player_position;
+        render.camera = self.camera;
+    }
+}
+
+/// Loads the map at `path`, on error the previous map stays loaded
 fn switch_map(
     loader: &mut tiled::Loader,
     path: &str,
     spawn_number: u32,
-    lang: &I18n,
+    lang: &Translator,
     render: &mut RenderingState,
     player: &mut PlayerState,
     enemies: &mut EnemiesState,
     physics: &mut PhysicsState,
-) -> tiled::Map {
-    let map = loader.load_tmx_map(path).unwrap();
+    lighting: &mut LightingState,
+    flags: &FlagStore,
+    audio: &mut AudioState,
+) -> Result<tiled::Map, MapLoadError> {
+    let map = loader
+        .load_tmx_map(path)
+        .map_err(|e| MapLoadError::new(MapLoadErrorKind::Parse(e.to_string())).in_map(path))?;
+    let previous = PreviousMap::take(render, player, enemies, physics, lighting);
+
+    let result = (|| -> Result<_, MapLoadError> {
+        load_tilemap_to_textures(render, &map);
+        load_tilemap_to_text_hints(render, &map, &lang)?;
+        load_tilemap_to_physics(physics, &map)?;
+        load_tilemap_to_interactables(physics, &map)?;
+        load_tilemap_to_enemies(enemies, &map, render)?;
+        load_tilemap_to_lights(lighting, &map)?;
+        load_player_spawn(player, &map, spawn_number)?;
+        Ok((load_tilemap_music(&map)?, load_tilemap_ambient_sounds(&map)?))
+    })();
+    let (music, ambient) = match result {
+        Ok(sounds) => sounds,
+        Err(e) => {
+            previous.restore(render, player, enemies, physics, lighting);
+            return Err(e.in_map(path));
+        }
+    };
+    play_music(audio, music.as_deref(), CROSSFADE_TIME);
+    set_ambient_sounds(audio, ambient);
+
     player.spawn_point = spawn_number;
     player.current_map = path.to_owned();
-
-    render.text_hints.clear();
-    physics.colliders.clear();
-    physics.interactables.clear();
-    enemies.enemies.clear();
-
-    load_tilemap_to_textures(render, &map);
-    load_tilemap_to_text_hints(render, &map, &lang);
-    load_tilemap_to_physics(physics, &map);
-    load_tilemap_to_interactables(physics, &map);
-    load_tilemap_to_enemies(enemies, &map, render);
-    load_player_spawn(player, &map, spawn_number);
-    return map;
+    open_doors(flags, physics, path);
+    release_map_textures(render, previous.textures);
+    return Ok(map);
 }
 
 fn move_player(player: &mut PlayerState, input: &InputState) {
     let mut wanna_move = false;
-    if get_key(sdl2::keyboard::Keycode::Left, input) {
+    let (stick_x, stick_y) = gamepad_stick(&input.gamepad);
+    if stick_x != 0.0 {
+        // a slightly tilted stick walks slowly
+        player.wants_dir = stick_x;
+        wanna_move = true;
+    }
+    if get_action(InputAction::MoveLeft, input) {
         player.wants_dir = -1.0;
         wanna_move = true;
     }
-    if get_key(sdl2::keyboard::Keycode::Right, input) {
+    if get_action(InputAction::MoveRight, input) {
         player.wants_dir = 1.0;
         wanna_move = true;
     }
-    if get_key_pressed(sdl2::keyboard::Keycode::Z, input) {
+    if get_action_pressed(InputAction::Jump, input) {
         player.wants_to_jump = true;
     } else {
         player.wants_to_jump = false;
@@ -341,13 +560,111 @@ fn move_player(player: &mut PlayerState, input: &InputState) {
         }
         player.wants_dir = 0.0;
     }
-    player.wants_to_interact = get_key(sdl2::keyboard::Keycode::Up, input);
+    player.wants_to_interact = get_action(InputAction::Interact, input);
+    player.wants_vertical = stick_y;
+    if get_action(InputAction::MoveUp, input) {
+        player.wants_vertical -= 1.0;
+    }
+    if get_action(InputAction::MoveDown, input) {
+        player.wants_vertical += 1.0;
+    }
+}
+
+fn run_console_command(
+    command: ConsoleCommand,
+    console: &mut ConsoleState,
+    loader: &mut tiled::Loader,
+    lang: &mut Translator,
+    settings: &mut Settings,
+    render: &mut RenderingState,
+    player: &mut PlayerState,
+    enemies: &mut EnemiesState,
+    physics: &mut PhysicsState,
+    lighting: &mut LightingState,
+    dialog: &mut DialogState,
+    error_screen: &mut ErrorScreenState,
+    flags: &FlagStore,
+    audio: &mut AudioState,
+) -> Option<tiled::Map> {
+    match command {
+        ConsoleCommand::Help => console_print(console, HELP),
+        ConsoleCommand::LoadMap(path, spawn_number) => {
+            if !std::path::Path::new(&path).exists() {
+                console_print(console, &format!("no such map: {}", path));
+                return None;
+            }
+            match switch_map(
+                loader, &path, spawn_number, lang, render, player, enemies, physics, lighting, flags, audio,
+            ) {
+                Ok(map) => return Some(map),
+                Err(e) => {
+                    console_print(console, &e.to_string());
+                    show_map_error(error_screen, e);
+                }
+            }
+        }
+        ConsoleCommand::Teleport(x, y) => {
+            player.x = x;
+            player.y = y;
+            player.velocity = glam::Vec2::ZERO;
+        }
+        ConsoleCommand::Noclip => {
+            player.noclip = !player.noclip;
+            console_print(console, &format!("noclip {}", player.noclip));
+        }
+        ConsoleCommand::God => {
+            player.god_mode = !player.god_mode;
+            console_print(console, &format!("god mode {}", player.god_mode));
+        }
+        ConsoleCommand::Lang(code) => {
+            if LOCALES.contains(&code.as_str()) {
+                change_language(&code, lang, render, dialog, enemies.enemies.len());
+                settings.language_override = Some(code.clone());
+                console_print(console, &format!("language set to {}", code));
+            } else {
+                console_print(console, &format!("unknown language: {}", code));
+            }
+        }
+        ConsoleCommand::Spawn(kind, position) => {
+            let (x, y) = position.unwrap_or((player.x + 24.0, player.y));
+            if let Err(e) = spawn_enemy(enemies, render, &kind, x, y) {
+                console_print(console, &e);
+            }
+        }
+        ConsoleCommand::TimeScale(scale) => {
+            physics.time_scale = scale.max(0.0);
+            console_print(console, &format!("time scale {}", physics.time_scale));
+        }
+        ConsoleCommand::ReloadDialogues => {
+            match load_dialogues() {
+                Ok(dialogues) => {
+                    dialog.dialogues = dialogues;
+                    console_print(console, "dialogues reloaded");
+                }
+                Err(e) => console_print(console, &e),
+            }
+            dialog.speakers = load_speakers();
+        }
+        ConsoleCommand::MissingTranslations => {
+            let missing = lang.missing_keys();
+            if missing.is_empty() {
+                console_print(console, "no missing translations");
+            }
+            for (locale, key) in missing {
+                console_print(console, &format!("{}: {}", locale, key));
+            }
+        }
+    }
+    None
 }
 
 fn input(state: &mut InputState) {
     let event_pump = &mut state.event_pump;
     state.key_pressed_state.clear();
     state.key_released_state.clear();
+    state.gamepad.button_pressed_state.clear();
+    state.window_resized = false;
+    state.text_input.clear();
     for event in event_pump.poll_iter() {
         match event {
             Event::Quit { .. } => {
@@ -357,17 +674,41 @@ fn input(state: &mut InputState) {
             Event::KeyDown { keycode: key, .. } => {
                 state.key_pressed_state.insert(key.unwrap(), true);
                 state.key_state.insert(key.unwrap(), true);
+                state.last_device = InputDevice::Keyboard;
             }
             Event::KeyUp { keycode: key, .. } => {
                 state.key_released_state.insert(key.unwrap(), false);
                 state.key_state.insert(key.unwrap(), false);
             }
+            Event::ControllerDeviceAdded { which, .. } => connect_gamepad(&mut state.gamepad, which),
+            Event::ControllerDeviceRemoved { which, .. } => disconnect_gamepad(&mut state.gamepad, which),
+            Event::ControllerButtonDown { button, .. } => {
+                state.gamepad.button_pressed_state.insert(button, true);
+                state.gamepad.button_state.insert(button, true);
+                state.last_device = InputDevice::Gamepad;
+            }
+            Event::ControllerButtonUp { button, .. } => {
+                state.gamepad.button_state.insert(button, false);
+            }
+            Event::ControllerAxisMotion { axis, value, .. } => {
+                let tilted = move_stick(&mut state.gamepad, axis, value);
+                if tilted {
+                    state.last_device = InputDevice::Gamepad;
+                }
+            }
+            Event::TextInput { text, .. } => {
+                state.text_input.push_str(&text);
+            }
+            Event::Window {
+                win_event: WindowEvent::SizeChanged(..),
+                ..
+            } => {
+                state.window_resized = true;
+            }
             _ => {}
         }
     }
-    if get_key(Keycode::Escape, state) {
-        state.should_quit = true;
-    }
+    update_stick(&mut state.gamepad);
 }
 
 fn get_key(key: sdl2::keyboard::Keycode, input: &InputState) -> bool {
@@ -385,3 +726,24 @@ fn get_key_pressed(key: sdl2::keyboard::Keycode, input: &InputState) -> bool {
         return false;
     };
 }
+
+fn get_button(button: sdl2::controller::Button, input: &InputState) -> bool {
+    input.gamepad.button_state.get(&button).copied().unwrap_or(false)
+}
+
+fn get_button_pressed(button: sdl2::controller::Button, input: &InputState) -> bool {
+    input.gamepad.button_pressed_state.get(&button).copied().unwrap_or(false)
+}
+
+/// Whether any key or button bound to `action` is held, the stick is read on its own with `gamepad_stick`
+fn get_action(action: InputAction, input: &InputState) -> bool {
+    input.bindings.keys(action).iter().any(|key| get_key(*key, input))
+        || input.bindings.buttons(action).iter().any(|button| get_button(*button, input))
+}
+
+/// Whether any key or button bound to `action` was pressed this frame, pushing the stick presses directions
+fn get_action_pressed(action: InputAction, input: &InputState) -> bool {
+    input.bindings.keys(action).iter().any(|key| get_key_pressed(*key, input))
+        || input.bindings.buttons(action).iter().any(|button| get_button_pressed(*button, input))
+        || input.gamepad.stick_pressed.contains(&action)
+}
@@ -0,0 +1,244 @@
+use std::fmt;
+
+use fontdue::layout::{Layout, LayoutSettings};
+use project_tomato::{type_name, Property, PropertyType};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
+use tiled::{Properties, PropertyValue};
+
+use crate::{
+    bindings::{Bindings, InputAction},
+    fonts::FontRegistry,
+    gamepad::InputDevice,
+    get_action_pressed,
+    i18n::Translator,
+    render::RenderingState,
+    InputState,
+};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum MapLoadErrorKind {
+    /// tiled could not read the file
+    Parse(String),
+    MissingProperty,
+    WrongType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    InvalidValue(String),
+    SpawnNotFound(u32),
+}
+
+#[derive(Clone, Debug)]
+pub struct MapLoadError {
+    pub map: String,
+    pub layer: Option<String>,
+    pub object: Option<u32>,
+    pub property: Option<String>,
+    pub kind: MapLoadErrorKind,
+}
+
+impl MapLoadError {
+    pub fn new(kind: MapLoadErrorKind) -> Self {
+        Self {
+            map: "".to_owned(),
+            layer: None,
+            object: None,
+            property: None,
+            kind,
+        }
+    }
+
+    pub fn in_map(mut self, map: &str) -> Self {
+        self.map = map.to_owned();
+        self
+    }
+
+    pub fn in_layer(mut self, layer: &str) -> Self {
+        self.layer = Some(layer.to_owned());
+        self
+    }
+
+    pub fn in_object(mut self, layer: &str, object: u32) -> Self {
+        self.object = Some(object);
+        self.in_layer(layer)
+    }
+
+    pub fn with_property(mut self, property: &str) -> Self {
+        self.property = Some(property.to_owned());
+        self
+    }
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.map)?;
+        if let Some(layer) = &self.layer {
+            write!(f, ", layer \"{}\"", layer)?;
+        }
+        if let Some(object) = self.object {
+            write!(f, ", object {}", object)?;
+        }
+        if let Some(property) = &self.property {
+            write!(f, ", property \"{}\"", property)?;
+        }
+        match &self.kind {
+            MapLoadErrorKind::Parse(message) => write!(f, ": {}", message),
+            MapLoadErrorKind::MissingProperty => write!(f, ": missing"),
+            MapLoadErrorKind::WrongType { expected, found } => {
+                write!(f, ": expected {}, found {}", expected, found)
+            }
+            MapLoadErrorKind::InvalidValue(message) => write!(f, ": {}", message),
+            MapLoadErrorKind::SpawnNotFound(spawn) => write!(f, ": spawn place {} not found", spawn),
+        }
+    }
+}
+
+/// Wrong types are reported with the type `property` is read as
+fn wrong_type(property: Property, value: &PropertyValue) -> MapLoadError {
+    MapLoadError::new(MapLoadErrorKind::WrongType {
+        expected: property.kind.name(),
+        found: type_name(value),
+    })
+    .with_property(property.name)
+}
+
+/// Turns a missing optional property into an error
+pub fn required<T>(value: Result<Option<T>, MapLoadError>, property: Property) -> Result<T, MapLoadError> {
+    value?.ok_or_else(|| MapLoadError::new(MapLoadErrorKind::MissingProperty).with_property(property.name))
+}
+
+pub fn int_property(properties: &Properties, property: Property) -> Result<Option<i32>, MapLoadError> {
+    debug_assert_eq!(property.kind, PropertyType::Int, "{}", property.name);
+    match properties.get(property.name) {
+        Some(PropertyValue::IntValue(value)) => Ok(Some(*value)),
+        Some(value) => Err(wrong_type(property, value)),
+        None => Ok(None),
+    }
+}
+
+/// Ints are accepted too
+pub fn float_property(properties: &Properties, property: Property) -> Result<Option<f32>, MapLoadError> {
+    debug_assert_eq!(property.kind, PropertyType::Float, "{}", property.name);
+    match properties.get(property.name) {
+        Some(PropertyValue::FloatValue(value)) => Ok(Some(*value)),
+        Some(PropertyValue::IntValue(value)) => Ok(Some(*value as f32)),
+        Some(value) => Err(wrong_type(property, value)),
+        None => Ok(None),
+    }
+}
+
+/// Font name, or its index in the registry for older maps
+pub fn font_property(
+    properties: &Properties,
+    property: Property,
+    fonts: &FontRegistry,
+) -> Result<Option<usize>, MapLoadError> {
+    debug_assert_eq!(property.kind, PropertyType::Font, "{}", property.name);
+    let invalid =
+        |message: String| MapLoadError::new(MapLoadErrorKind::InvalidValue(message)).with_property(property.name);
+    match properties.get(property.name) {
+        Some(PropertyValue::StringValue(font)) => fonts
+            .index(font)
+            .map(Some)
+            .ok_or_else(|| invalid(format!("no font named {}", font))),
+        Some(PropertyValue::IntValue(index)) => {
+            if *index >= 0 && (*index as usize) < fonts.as_slice().len() {
+                Ok(Some(*index as usize))
+            } else {
+                Err(invalid(format!("no font with index {}", index)))
+            }
+        }
+        Some(value) => Err(wrong_type(property, value)),
+        None => Ok(None),
+    }
+}
+
+pub fn string_property<'a>(
+    properties: &'a Properties,
+    property: Property,
+) -> Result<Option<&'a String>, MapLoadError> {
+    debug_assert_eq!(property.kind, PropertyType::String, "{}", property.name);
+    match properties.get(property.name) {
+        Some(PropertyValue::StringValue(value)) => Ok(Some(value)),
+        Some(value) => Err(wrong_type(property, value)),
+        None => Ok(None),
+    }
+}
+
+pub fn color_property(properties: &Properties, property: Property) -> Result<Option<Color>, MapLoadError> {
+    debug_assert_eq!(property.kind, PropertyType::Color, "{}", property.name);
+    match properties.get(property.name) {
+        Some(PropertyValue::ColorValue(color)) => Ok(Some(Color::RGB(color.red, color.green, color.blue))),
+        Some(value) => Err(wrong_type(property, value)),
+        None => Ok(None),
+    }
+}
+
+pub struct ErrorScreenState {
+    pub error: Option<MapLoadError>,
+    layout: Layout<Color>,
+}
+
+impl ErrorScreenState {
+    pub fn new() -> Self {
+        Self {
+            error: None,
+            layout: Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown),
+        }
+    }
+}
+
+pub fn show_map_error(state: &mut ErrorScreenState, error: MapLoadError) {
+    println!("{}", error);
+    state.error = Some(error);
+}
+
+/// Closes the error screen with confirm or cancel
+pub fn update_error_screen(state: &mut ErrorScreenState, input: &InputState) {
+    if get_action_pressed(InputAction::Confirm, input) || get_action_pressed(InputAction::Cancel, input) {
+        state.error = None;
+    }
+}
+
+pub fn render_error_screen(
+    render: &mut RenderingState,
+    state: &mut ErrorScreenState,
+    lang: &Translator,
+    bindings: &Bindings,
+    device: InputDevice,
+) {
+    let error = if let Some(error) = &state.error {
+        error
+    } else {
+        return;
+    };
+    let (canvas_w, canvas_h) = render.canvas.logical_size();
+    let margin = 8u32;
+
+    render.canvas.set_blend_mode(BlendMode::Blend);
+    render.canvas.set_draw_color(Color::RGBA(40, 0, 0, 220));
+    render.canvas.fill_rect(Rect::new(0, 0, canvas_w, canvas_h)).unwrap();
+    render.canvas.set_blend_mode(BlendMode::None);
+
+    state.layout.reset(&LayoutSettings {
+        x: margin as f32,
+        y: margin as f32,
+        max_width: Some((canvas_w - 2 * margin) as f32),
+        ..LayoutSettings::default()
+    });
+    let title = lang.t("map-error-title");
+    render.fonts.append(&mut state.layout, &format!("{}\n\n", title.text), 8.0, 0, title.color(Color::RED));
+    render.fonts.append(&mut state.layout, &format!("{}\n\n", error), 8.0, 0, Color::WHITE);
+    // Without a confirm binding on this device there is no key to name, cancel still closes the screen
+    if let Some(binding) = bindings.bindings(InputAction::Confirm, device).first() {
+        let key = binding.name().to_uppercase();
+        let prompt = lang.format("map-error-continue", &[("key", key.as_str().into())]);
+        render.fonts.append(&mut state.layout, &prompt.text, 8.0, 0, prompt.color(Color::YELLOW));
+    }
+    render
+        .font_texture
+        .draw_text(&mut render.canvas, render.fonts.as_slice(), state.layout.glyphs())
+        .unwrap();
+}
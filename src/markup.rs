@@ -0,0 +1,277 @@
+use fontdue::layout::GlyphPosition;
+use sdl2::pixels::Color;
+
+/// Inline icons usable with `[icon=name]`
+pub const ICONS: &[(&str, &str)] = &[("arrow", "res/arrowglyph.png")];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextEffect {
+    None,
+    /// Glyphs bob up and down one after another
+    Wave,
+    /// Glyphs jitter randomly
+    Shake,
+}
+
+/// Styled span of the plain text, `start` and `end` are char indices
+#[derive(Clone, PartialEq, Debug)]
+pub struct TextRun {
+    pub start: usize,
+    pub end: usize,
+    pub color: Color,
+    pub effect: TextEffect,
+    /// Typewriter speed multiplier
+    pub speed: f32,
+}
+
+/// Typewriter stops for `seconds` after revealing `at` chars
+#[derive(Clone, PartialEq, Debug)]
+pub struct Pause {
+    pub at: usize,
+    pub seconds: f32,
+}
+
+/// Spaces that make room for an inline icon, non-breaking so word wrap keeps them on one line
+pub const ICON_PLACEHOLDER: &str = "\u{a0}\u{a0}\u{a0}";
+
+/// Icon drawn over the placeholder starting at char index `at`
+#[derive(Clone, PartialEq, Debug)]
+pub struct InlineIcon {
+    pub at: usize,
+    pub texture: String,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Markup {
+    pub runs: Vec<TextRun>,
+    pub pauses: Vec<Pause>,
+    pub icons: Vec<InlineIcon>,
+}
+
+impl Markup {
+    pub fn run_at(&self, index: usize) -> Option<&TextRun> {
+        self.runs.iter().find(|run| run.start <= index && index < run.end)
+    }
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+    match value {
+        "white" => Some(Color::WHITE),
+        "red" => Some(Color::RGB(255, 80, 80)),
+        "green" => Some(Color::RGB(80, 255, 80)),
+        "blue" => Some(Color::RGB(100, 150, 255)),
+        "yellow" => Some(Color::YELLOW),
+        "orange" => Some(Color::RGB(255, 160, 40)),
+        "gray" => Some(Color::GRAY),
+        hex if hex.len() == 7 && hex.starts_with('#') => {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+            Some(Color::RGB(channel(1)?, channel(3)?, channel(5)?))
+        }
+        _ => None,
+    }
+}
+
+/// Parses `[color=red]..[/color]`, `[wave]..[/wave]`, `[shake]..[/shake]`, `[speed=2]..[/speed]`,
+/// `[pause=0.5]` and `[icon=arrow]`, `[[` is a literal `[`.
+/// Unknown tags are kept as text. Returns the plain text and its styling.
+pub fn parse_markup(source: &str, default_color: Color) -> (String, Markup) {
+    let mut text = String::new();
+    let mut markup = Markup::default();
+    let mut len = 0;
+    let mut colors = vec![default_color];
+    let mut effects = vec![TextEffect::None];
+    let mut speeds = vec![1.0];
+    let mut run_start = 0;
+
+    let mut rest = source;
+    while let Some(open) = rest.find('[') {
+        let before = &rest[..open];
+        text.push_str(before);
+        len += before.chars().count();
+        rest = &rest[open..];
+        if let Some(after) = rest.strip_prefix("[[") {
+            text.push('[');
+            len += 1;
+            rest = after;
+            continue;
+        }
+        let close = match rest.find(']') {
+            Some(close) => close,
+            None => break,
+        };
+        let tag = &rest[1..close];
+        let (name, value) = match tag.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (tag, None),
+        };
+        let style = current_style(&colors, &effects, &speeds);
+        let known = match (name, value) {
+            ("color", Some(value)) => parse_color(value).map(|color| colors.push(color)).is_some(),
+            ("/color", None) if colors.len() > 1 => colors.pop().is_some(),
+            ("wave", None) => {
+                effects.push(TextEffect::Wave);
+                true
+            }
+            ("shake", None) => {
+                effects.push(TextEffect::Shake);
+                true
+            }
+            ("/wave", None) | ("/shake", None) if effects.len() > 1 => effects.pop().is_some(),
+            ("speed", Some(value)) => value.parse().map(|speed: f32| speeds.push(speed.max(0.01))).is_ok(),
+            ("/speed", None) if speeds.len() > 1 => speeds.pop().is_some(),
+            ("pause", Some(value)) => value
+                .parse()
+                .map(|seconds| markup.pauses.push(Pause { at: len, seconds }))
+                .is_ok(),
+            ("icon", Some(value)) => match ICONS.iter().find(|(icon, _)| *icon == value) {
+                Some((_, texture)) => {
+                    markup.icons.push(InlineIcon { at: len, texture: texture.to_string() });
+                    text.push_str(ICON_PLACEHOLDER);
+                    len += ICON_PLACEHOLDER.chars().count();
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        };
+        if !known {
+            // keep it as text
+            text.push('[');
+            len += 1;
+            rest = &rest[1..];
+            continue;
+        }
+        rest = &rest[close + 1..];
+        if style != current_style(&colors, &effects, &speeds) {
+            push_run(&mut markup, run_start, len, style);
+            run_start = len;
+        }
+    }
+    text.push_str(rest);
+    len += rest.chars().count();
+    push_run(&mut markup, run_start, len, current_style(&colors, &effects, &speeds));
+    (text, markup)
+}
+
+fn current_style(colors: &[Color], effects: &[TextEffect], speeds: &[f32]) -> (Color, TextEffect, f32) {
+    (*colors.last().unwrap(), *effects.last().unwrap(), *speeds.last().unwrap())
+}
+
+fn push_run(markup: &mut Markup, start: usize, end: usize, style: (Color, TextEffect, f32)) {
+    if start < end {
+        let (color, effect, speed) = style;
+        markup.runs.push(TextRun { start, end, color, effect, speed });
+    }
+}
+
+/// Offsets glyphs of wave and shake runs, glyph indices match char indices of the text
+pub fn apply_text_effects(glyphs: &mut [GlyphPosition<Color>], markup: &Markup, time: f32) {
+    for run in markup.runs.iter() {
+        if run.effect == TextEffect::None {
+            continue;
+        }
+        for i in run.start..run.end.min(glyphs.len()) {
+            let glyph = &mut glyphs[i];
+            match run.effect {
+                TextEffect::Wave => glyph.y += (time * 8.0 - i as f32 * 0.6).sin() * 1.5,
+                TextEffect::Shake => {
+                    // cheap hash of the glyph index and time, changes 20 times a second
+                    let seed = (i as u32).wrapping_mul(2654435761) ^ ((time * 20.0) as u32).wrapping_mul(40503);
+                    glyph.x += (seed % 3) as f32 - 1.0;
+                    glyph.y += ((seed >> 8) % 3) as f32 - 1.0;
+                }
+                TextEffect::None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fontdue::layout::{CoordinateSystem, Layout, LayoutSettings, TextStyle};
+    use fontdue::Font;
+
+    fn run(start: usize, end: usize, color: Color, effect: TextEffect, speed: f32) -> TextRun {
+        TextRun { start, end, color, effect, speed }
+    }
+
+    #[test]
+    fn colors_are_parsed() {
+        let (text, markup) = parse_markup("[color=red]Hot[/color] and [color=#102030]dark[/color]", Color::WHITE);
+        assert_eq!(text, "Hot and dark");
+        assert_eq!(
+            markup.runs,
+            vec![
+                run(0, 3, Color::RGB(255, 80, 80), TextEffect::None, 1.0),
+                run(3, 8, Color::WHITE, TextEffect::None, 1.0),
+                run(8, 12, Color::RGB(0x10, 0x20, 0x30), TextEffect::None, 1.0),
+            ]
+        );
+        assert_eq!(markup.run_at(9).unwrap().color, Color::RGB(0x10, 0x20, 0x30));
+        assert_eq!(markup.run_at(12), None);
+    }
+
+    #[test]
+    fn effects_nest() {
+        let (text, markup) = parse_markup("[wave]a[shake]b[/shake]c[/wave]d", Color::WHITE);
+        assert_eq!(text, "abcd");
+        let effects: Vec<(usize, usize, TextEffect)> = markup.runs.iter().map(|r| (r.start, r.end, r.effect)).collect();
+        assert_eq!(
+            effects,
+            vec![(0, 1, TextEffect::Wave), (1, 2, TextEffect::Shake), (2, 3, TextEffect::Wave), (3, 4, TextEffect::None)]
+        );
+    }
+
+    #[test]
+    fn speed_and_pauses() {
+        let (text, markup) = parse_markup("[speed=2]ab[/speed]c[pause=0.5]d[speed=0]e", Color::WHITE);
+        assert_eq!(text, "abcde");
+        let speeds: Vec<f32> = markup.runs.iter().map(|r| r.speed).collect();
+        assert_eq!(speeds, vec![2.0, 1.0, 0.01]);
+        assert_eq!(markup.pauses, vec![Pause { at: 3, seconds: 0.5 }]);
+    }
+
+    #[test]
+    fn icons_make_room_in_the_text() {
+        let (text, markup) = parse_markup("Press [icon=arrow] to [icon=nope]", Color::WHITE);
+        assert_eq!(text, format!("Press {} to [icon=nope]", ICON_PLACEHOLDER));
+        assert_eq!(markup.icons, vec![InlineIcon { at: 6, texture: "res/arrowglyph.png".to_owned() }]);
+        assert_eq!(markup.runs, vec![run(0, text.chars().count(), Color::WHITE, TextEffect::None, 1.0)]);
+    }
+
+    #[test]
+    fn escapes_and_unknown_tags_stay_text() {
+        let (text, markup) = parse_markup("[[wave] [b]x[/b] [color=nope]y [pause=z] [wave", Color::WHITE);
+        assert_eq!(text, "[wave] [b]x[/b] [color=nope]y [pause=z] [wave");
+        assert_eq!(markup.runs.len(), 1);
+        assert!(markup.pauses.is_empty());
+    }
+
+    #[test]
+    fn mismatched_closers_stay_text() {
+        let (text, markup) = parse_markup("[/color]a[wave]b[/color][/speed]c[/wave]d", Color::WHITE);
+        assert_eq!(text, "[/color]ab[/color][/speed]cd");
+        let effects: Vec<(usize, usize, TextEffect)> = markup.runs.iter().map(|r| (r.start, r.end, r.effect)).collect();
+        assert_eq!(effects, vec![(0, 9, TextEffect::None), (9, 27, TextEffect::Wave), (27, 28, TextEffect::None)]);
+        assert!(markup.runs.iter().all(|r| r.color == Color::WHITE && r.speed == 1.0));
+    }
+
+    #[test]
+    fn word_wrap_keeps_icons_on_one_line() {
+        let font = Font::from_bytes(&include_bytes!("../res/kongtext.ttf")[..], Default::default()).unwrap();
+        let (text, markup) = parse_markup("Press [icon=arrow] to jump over the [icon=arrow] hole", Color::WHITE);
+        let mut layout: Layout<()> = Layout::new(CoordinateSystem::PositiveYDown);
+        for width in 64..400 {
+            layout.reset(&LayoutSettings { max_width: Some(width as f32), ..LayoutSettings::default() });
+            layout.append(&[&font], &TextStyle::new(&text, 8.0, 0));
+            let glyphs = layout.glyphs();
+            for icon in markup.icons.iter() {
+                let y = glyphs[icon.at].y;
+                for glyph in &glyphs[icon.at..icon.at + ICON_PLACEHOLDER.chars().count()] {
+                    assert_eq!(glyph.y, y, "width {}", width);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use fontdue::layout::{Layout, LayoutSettings};
+use sdl2::controller::Button;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::{
+    bindings::{Binding, Bindings, InputAction, ACTIONS, RESERVED_BUTTONS, RESERVED_KEYS},
+    gamepad::InputDevice,
+    get_action_pressed, get_button_pressed, get_key_pressed,
+    i18n::{Translation, Translator, LOCALES},
+    render::RenderingState,
+    settings::{AspectMode, Settings, WindowMode, RESOLUTIONS},
+    InputState,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MenuItem {
+    Resume,
+    WindowMode,
+    Resolution,
+    IntegerScale,
+    AspectMode,
+    Vsync,
+    MasterVolume,
+    MusicVolume,
+    SfxVolume,
+    Language,
+    Controls,
+    Quit,
+}
+
+impl MenuItem {
+    /// Translation key of the label
+    fn label(&self) -> &'static str {
+        match self {
+            MenuItem::Resume => "menu-resume",
+            MenuItem::WindowMode => "menu-window-mode",
+            MenuItem::Resolution => "menu-resolution",
+            MenuItem::IntegerScale => "menu-integer-scale",
+            MenuItem::AspectMode => "menu-aspect-mode",
+            MenuItem::Vsync => "menu-vsync",
+            MenuItem::MasterVolume => "menu-master-volume",
+            MenuItem::MusicVolume => "menu-music-volume",
+            MenuItem::SfxVolume => "menu-sfx-volume",
+            MenuItem::Language => "menu-language",
+            MenuItem::Controls => "menu-controls",
+            MenuItem::Quit => "menu-quit",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum MenuPage {
+    Main,
+    /// Keys of every action, then reset and back
+    Controls,
+}
+
+pub struct MenuState {
+    pub open: bool,
+    pub selected: usize,
+    pub items: Vec<MenuItem>,
+    page: MenuPage,
+    controls_selected: usize,
+    /// Action whose new key or button is the next one pressed
+    pub waiting_for_input: Option<InputAction>,
+    layout: Layout<Color>,
+}
+
+impl MenuState {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            selected: 0,
+            items: vec![
+                MenuItem::Resume,
+                MenuItem::WindowMode,
+                MenuItem::Resolution,
+                MenuItem::IntegerScale,
+                MenuItem::AspectMode,
+                MenuItem::Vsync,
+                MenuItem::MasterVolume,
+                MenuItem::MusicVolume,
+                MenuItem::SfxVolume,
+                MenuItem::Language,
+                MenuItem::Controls,
+                MenuItem::Quit,
+            ],
+            page: MenuPage::Main,
+            controls_selected: 0,
+            waiting_for_input: None,
+            layout: Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown),
+        }
+    }
+}
+
+pub enum MenuResult {
+    Nothing,
+    SettingsChanged,
+    /// Only volumes changed, the window does not need to be touched
+    VolumeChanged,
+    /// `Settings::language` changed, translated text needs to be laid out again
+    LanguageChanged,
+    /// `action` should be bound to a new key or button instead of the current ones of that device
+    Rebind(InputAction, Binding),
+    ResetBindings,
+    Quit,
+}
+
+/// Opens or closes the menu, it always opens on the main page
+pub fn toggle_menu(menu: &mut MenuState) {
+    menu.open = !menu.open;
+    menu.page = MenuPage::Main;
+    menu.waiting_for_input = None;
+}
+
+pub fn update_menu(menu: &mut MenuState, input: &InputState, settings: &mut Settings) -> MenuResult {
+    if menu.page == MenuPage::Controls {
+        return update_controls(menu, input);
+    }
+    if get_action_pressed(InputAction::MoveUp, input) {
+        menu.selected = (menu.selected + menu.items.len() - 1) % menu.items.len();
+    }
+    if get_action_pressed(InputAction::MoveDown, input) {
+        menu.selected = (menu.selected + 1) % menu.items.len();
+    }
+    if get_action_pressed(InputAction::Cancel, input) {
+        menu.open = false;
+        return MenuResult::Nothing;
+    }
+    let confirm = get_action_pressed(InputAction::Confirm, input);
+    let step: i32 = if get_action_pressed(InputAction::MoveLeft, input) {
+        -1
+    } else if get_action_pressed(InputAction::MoveRight, input) || confirm {
+        1
+    } else {
+        return MenuResult::Nothing;
+    };
+
+    let video = &mut settings.video;
+    let audio = &mut settings.audio;
+    let change_volume = |volume: &mut f32| {
+        *volume = ((*volume * 10.0).round() + step as f32).clamp(0.0, 10.0) / 10.0;
+        MenuResult::VolumeChanged
+    };
+    match menu.items[menu.selected] {
+        MenuItem::Resume => {
+            if confirm {
+                menu.open = false;
+            }
+            return MenuResult::Nothing;
+        }
+        MenuItem::Controls => {
+            if confirm {
+                menu.page = MenuPage::Controls;
+                menu.controls_selected = 0;
+            }
+            return MenuResult::Nothing;
+        }
+        MenuItem::Quit => {
+            if confirm {
+                return MenuResult::Quit;
+            }
+            return MenuResult::Nothing;
+        }
+        MenuItem::WindowMode => {
+            let modes = [WindowMode::Windowed, WindowMode::Fullscreen, WindowMode::Borderless];
+            video.window_mode = cycle(&modes, video.window_mode, step);
+        }
+        MenuItem::Resolution => {
+            let current = (video.width, video.height);
+            let (width, height) = cycle(&RESOLUTIONS, current, step);
+            video.width = width;
+            video.height = height;
+        }
+        MenuItem::IntegerScale => video.integer_scale = !video.integer_scale,
+        MenuItem::AspectMode => {
+            let modes = [AspectMode::Letterbox, AspectMode::Expand];
+            video.aspect_mode = cycle(&modes, video.aspect_mode, step);
+        }
+        MenuItem::Vsync => video.vsync = !video.vsync,
+        MenuItem::MasterVolume => return change_volume(&mut audio.master),
+        MenuItem::MusicVolume => return change_volume(&mut audio.music),
+        MenuItem::SfxVolume => return change_volume(&mut audio.sfx),
+        MenuItem::Language => {
+            let language = cycle(LOCALES, settings.current_language(), step);
+            // picked here, so it is saved and replaces the override
+            settings.language = language.to_owned();
+            settings.language_override = None;
+            return MenuResult::LanguageChanged;
+        }
+    }
+    MenuResult::SettingsChanged
+}
+
+/// Picks an action and waits for its new key or button, Escape or Back stops waiting
+fn update_controls(menu: &mut MenuState, input: &InputState) -> MenuResult {
+    if let Some(action) = menu.waiting_for_input {
+        // hard-coded so a broken binding can always be backed out of
+        if get_key_pressed(Keycode::Escape, input) || get_button_pressed(Button::Back, input) {
+            menu.waiting_for_input = None;
+            return MenuResult::Nothing;
+        }
+        let key = input
+            .key_pressed_state
+            .iter()
+            .find(|(key, pressed)| **pressed && !RESERVED_KEYS.contains(key))
+            .map(|(key, _)| Binding::Key(*key));
+        let button = input
+            .gamepad
+            .button_pressed_state
+            .iter()
+            .find(|(button, pressed)| **pressed && !RESERVED_BUTTONS.contains(button))
+            .map(|(button, _)| Binding::Button(*button));
+        return match key.or(button) {
+            Some(binding) => {
+                menu.waiting_for_input = None;
+                MenuResult::Rebind(action, binding)
+            }
+            None => MenuResult::Nothing,
+        };
+    }
+
+    // every action, reset and back
+    let rows = ACTIONS.len() + 2;
+    if get_action_pressed(InputAction::MoveUp, input) {
+        menu.controls_selected = (menu.controls_selected + rows - 1) % rows;
+    }
+    if get_action_pressed(InputAction::MoveDown, input) {
+        menu.controls_selected = (menu.controls_selected + 1) % rows;
+    }
+    if get_action_pressed(InputAction::Cancel, input) {
+        menu.page = MenuPage::Main;
+        return MenuResult::Nothing;
+    }
+    if !get_action_pressed(InputAction::Confirm, input) {
+        return MenuResult::Nothing;
+    }
+    match ACTIONS.get(menu.controls_selected) {
+        Some(action) => menu.waiting_for_input = Some(*action),
+        None if menu.controls_selected == ACTIONS.len() => return MenuResult::ResetBindings,
+        None => menu.page = MenuPage::Main,
+    }
+    MenuResult::Nothing
+}
+
+/// Returns the value `step` places away from `current`, wrapping around
+fn cycle<T: Copy + PartialEq>(values: &[T], current: T, step: i32) -> T {
+    let index = values.iter().position(|v| *v == current).unwrap_or(0) as i32;
+    let len = values.len() as i32;
+    values[((index + step) % len + len) as usize % values.len()]
+}
+
+/// `vsync` is what the renderer does, it differs from the setting until a restart without `vsync-toggle`
+fn item_value(item: MenuItem, settings: &Settings, vsync: bool, lang: &Translator) -> String {
+    let on_off = |value: bool| {
+        let key = if value { "menu-on" } else { "menu-off" };
+        lang.t(key).text.into_owned()
+    };
+    let video = &settings.video;
+    match item {
+        MenuItem::Resume | MenuItem::Controls | MenuItem::Quit => "".to_owned(),
+        MenuItem::WindowMode => lang
+            .t(&format!("menu-{}", video.window_mode.name()))
+            .text
+            .into_owned(),
+        MenuItem::Resolution => format!("{}x{}", video.width, video.height),
+        MenuItem::IntegerScale => on_off(video.integer_scale),
+        MenuItem::AspectMode => lang
+            .t(&format!("menu-{}", video.aspect_mode.name()))
+            .text
+            .into_owned(),
+        MenuItem::Vsync if video.vsync != vsync => {
+            format!("{} ({})", on_off(video.vsync), lang.t("menu-applies-on-restart").text)
+        }
+        MenuItem::Vsync => on_off(video.vsync),
+        MenuItem::MasterVolume => format!("{}%", (settings.audio.master * 100.0).round()),
+        MenuItem::MusicVolume => format!("{}%", (settings.audio.music * 100.0).round()),
+        MenuItem::SfxVolume => format!("{}%", (settings.audio.sfx * 100.0).round()),
+        // every language names itself
+        MenuItem::Language => lang.t("language-name").text.into_owned(),
+    }
+}
+
+/// Menu line with its colour, `value` follows the label when it is not empty
+fn menu_line(label: Translation, value: &str, selected: bool, color: Color) -> (String, Color) {
+    let (prefix, color) = if selected { ("> ", Color::YELLOW) } else { ("  ", color) };
+    let color = label.color(color);
+    if value.is_empty() {
+        (format!("{}{}", prefix, label.text), color)
+    } else {
+        (format!("{}{}: {}", prefix, label.text, value), color)
+    }
+}
+
+/// Bindings of the device used last, so a gamepad shows buttons
+fn controls_lines(
+    menu: &MenuState,
+    bindings: &Bindings,
+    device: InputDevice,
+    lang: &Translator,
+) -> Vec<(String, Color)> {
+    let conflicts: Vec<_> = bindings
+        .conflicts()
+        .into_iter()
+        .filter(|conflict| conflict.binding.device() == device)
+        .collect();
+    let mut lines: Vec<(String, Color)> = ACTIONS
+        .iter()
+        .enumerate()
+        .map(|(i, action)| {
+            let selected = i == menu.controls_selected;
+            let value = if selected && menu.waiting_for_input.is_some() {
+                let key = if device == InputDevice::Gamepad { "controls-press-button" } else { "controls-press-key" };
+                lang.t(key).text.into_owned()
+            } else {
+                let names: Vec<String> = bindings
+                    .bindings(*action, device)
+                    .iter()
+                    .map(|binding| binding.name().to_uppercase())
+                    .collect();
+                names.join(", ")
+            };
+            let in_conflict = conflicts
+                .iter()
+                .any(|conflict| conflict.action == *action || conflict.other == Some(*action));
+            let color = if in_conflict { Color::RED } else { Color::WHITE };
+            menu_line(lang.t(action.label()), &value, selected, color)
+        })
+        .collect();
+    let selected = menu.controls_selected;
+    lines.push(menu_line(lang.t("controls-reset"), "", selected == ACTIONS.len(), Color::WHITE));
+    lines.push(menu_line(lang.t("controls-back"), "", selected == ACTIONS.len() + 1, Color::WHITE));
+
+    // the first conflict is explained under the list
+    if let Some(conflict) = conflicts.first() {
+        let key = conflict.binding.name().to_uppercase();
+        let first = lang.t(conflict.action.label());
+        let message = match conflict.other {
+            Some(other) => {
+                let second = lang.t(other.label());
+                lang.format(
+                    "controls-conflict",
+                    &[
+                        ("key", key.as_str().into()),
+                        ("first", first.text.as_ref().into()),
+                        ("second", second.text.as_ref().into()),
+                    ],
+                )
+                .text
+                .into_owned()
+            }
+            None => lang
+                .format("controls-reserved", &[("key", key.as_str().into())])
+                .text
+                .into_owned(),
+        };
+        lines.push((format!("  {}", message), Color::RED));
+    }
+    lines
+}
+
+pub fn render_menu(
+    render: &mut RenderingState,
+    menu: &mut MenuState,
+    settings: &Settings,
+    lang: &Translator,
+    bindings: &Bindings,
+    device: InputDevice,
+) {
+    if !menu.open {
+        return;
+    }
+    let lines = match menu.page {
+        MenuPage::Main => menu
+            .items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let value = item_value(*item, settings, render.vsync, lang);
+                menu_line(lang.t(item.label()), &value, i == menu.selected, Color::WHITE)
+            })
+            .collect(),
+        MenuPage::Controls => controls_lines(menu, bindings, device, lang),
+    };
+
+    let (canvas_w, canvas_h) = render.canvas.logical_size();
+    let font_size = 8.0;
+    let line_height = 12u32;
+    let width = 220u32.min(canvas_w);
+    let height = line_height * lines.len() as u32 + 10;
+    let x = (canvas_w as i32 - width as i32) / 2;
+    let y = (canvas_h as i32 - height as i32) / 2;
+
+    let bg = Rect::new(x, y, width, height);
+    render.canvas.set_draw_color(Color::BLACK);
+    render.canvas.fill_rect(bg).unwrap();
+    render.canvas.set_draw_color(Color::GREEN);
+    render.canvas.draw_rect(bg).unwrap();
+
+    for (i, (text, color)) in lines.iter().enumerate() {
+        menu.layout.reset(&LayoutSettings {
+            x: (x + 5) as f32,
+            y: (y + 5 + (i as u32 * line_height) as i32) as f32,
+            ..LayoutSettings::default()
+        });
+        render.fonts.append(&mut menu.layout, text, font_size, 0, *color);
+        render
+            .font_texture
+            .draw_text(&mut render.canvas, render.fonts.as_slice(), menu.layout.glyphs())
+            .unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+
+use glam::Vec2;
+use json::JsonValue;
+use sdl2::pixels::Color;
+use sdl2::render::BlendMode;
+
+use crate::render::{AnimationFrame, RenderingState};
+
+pub const PARTICLES_PATH: &str = "res/particles.json";
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum EmitterMode {
+    /// Spawns `count` particles every time the emitter is triggered
+    Burst(u32),
+    /// Spawns `rate` particles per second for as long as the emitter is triggered every frame
+    Continuous(f32),
+}
+
+#[derive(Clone)]
+pub struct EmitterDef {
+    pub mode: EmitterMode,
+    pub lifetime: (f32, f32),
+    pub speed: (f32, f32),
+    /// Direction of the velocity cone in degrees, 0 = right, 90 = down
+    pub angle: f32,
+    /// Width of the velocity cone in degrees
+    pub spread: f32,
+    pub gravity: f32,
+    pub size: u32,
+    /// Colour ramp interpolated over the particle lifetime
+    pub colors: Vec<Color>,
+    pub texture: Option<String>,
+    /// Sprite frames played once over the particle lifetime
+    pub frames: Vec<AnimationFrame>,
+}
+
+#[derive(Clone)]
+pub struct Particle {
+    pub emitter: usize,
+    pub x: f32,
+    pub y: f32,
+    pub velocity: Vec2,
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+#[derive(Clone)]
+pub struct ParticlesState {
+    pub emitters: Vec<EmitterDef>,
+    pub emitter_names: HashMap<String, usize>,
+    pub particles: Vec<Particle>,
+    /// Fractional particles carried over between frames by continuous emitters
+    pub accumulators: Vec<f32>,
+    pub dt: f32,
+    rng: u32,
+}
+
+impl ParticlesState {
+    pub fn new() -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let mut state = Self {
+            emitters: vec![],
+            emitter_names: HashMap::new(),
+            particles: vec![],
+            accumulators: vec![],
+            dt: 0.0,
+            rng: seed | 1,
+        };
+        let data = match std::fs::read_to_string(PARTICLES_PATH) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Failed to load {}: {}", PARTICLES_PATH, e);
+                return state;
+            }
+        };
+        let data = match json::parse(&data) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Failed to parse {}: {}", PARTICLES_PATH, e);
+                return state;
+            }
+        };
+        for (name, value) in data.entries() {
+            let emitter = match parse_emitter(value) {
+                Some(emitter) => emitter,
+                None => {
+                    println!("Particle emitter {} in {} has no valid mode", name, PARTICLES_PATH);
+                    continue;
+                }
+            };
+            state.emitter_names.insert(name.to_owned(), state.emitters.len());
+            state.emitters.push(emitter);
+            state.accumulators.push(0.0);
+        }
+        state
+    }
+
+    /// xorshift, good enough for particles
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng % 10000) as f32 / 10000.0
+    }
+
+    fn random_range(&mut self, range: (f32, f32)) -> f32 {
+        range.0 + (range.1 - range.0) * self.random()
+    }
+}
+
+/// None when `mode` is not `burst` or `continuous`
+fn parse_emitter(value: &JsonValue) -> Option<EmitterDef> {
+    fn range(value: &JsonValue, default: f32) -> (f32, f32) {
+        match value {
+            JsonValue::Array(arr) if arr.len() == 2 => (
+                arr[0].as_f32().unwrap_or(default),
+                arr[1].as_f32().unwrap_or(default),
+            ),
+            _ => {
+                let v = value.as_f32().unwrap_or(default);
+                (v, v)
+            }
+        }
+    }
+    let mode = match value["mode"].as_str() {
+        Some("burst") => EmitterMode::Burst(value["count"].as_u32().unwrap_or(1)),
+        Some("continuous") => EmitterMode::Continuous(value["rate"].as_f32().unwrap_or(1.0)),
+        _ => return None,
+    };
+    let colors: Vec<Color> = value["colors"]
+        .members()
+        .map(|c| {
+            Color::RGBA(
+                c[0].as_u8().unwrap_or(255),
+                c[1].as_u8().unwrap_or(255),
+                c[2].as_u8().unwrap_or(255),
+                c[3].as_u8().unwrap_or(255),
+            )
+        })
+        .collect();
+    let frames = value["frames"]
+        .members()
+        .map(|f| AnimationFrame {
+            x: f[0].as_i32().unwrap_or(0),
+            y: f[1].as_i32().unwrap_or(0),
+            w: f[2].as_u32().unwrap_or(1),
+            h: f[3].as_u32().unwrap_or(1),
+        })
+        .collect();
+    Some(EmitterDef {
+        mode,
+        lifetime: range(&value["lifetime"], 1.0),
+        speed: range(&value["speed"], 0.0),
+        angle: value["angle"].as_f32().unwrap_or(0.0),
+        spread: value["spread"].as_f32().unwrap_or(360.0),
+        gravity: value["gravity"].as_f32().unwrap_or(0.0),
+        size: value["size"].as_u32().unwrap_or(1),
+        colors: if colors.is_empty() { vec![Color::WHITE] } else { colors },
+        texture: value["texture"].as_str().map(|s| s.to_owned()),
+        frames,
+    })
+}
+
+/// Triggers emitter `name` at the given world position.
+/// Burst emitters spawn all of their particles at once,
+/// continuous ones have to be triggered every frame to keep emitting.
+pub fn emit_particles(state: &mut ParticlesState, name: &str, x: f32, y: f32) {
+    let index = if let Some(index) = state.emitter_names.get(name) {
+        *index
+    } else {
+        println!("Unknown particle emitter: {}", name);
+        return;
+    };
+    let count = match state.emitters[index].mode {
+        EmitterMode::Burst(count) => count,
+        EmitterMode::Continuous(rate) => {
+            state.accumulators[index] += rate * state.dt;
+            let count = state.accumulators[index].floor();
+            state.accumulators[index] -= count;
+            count as u32
+        }
+    };
+    for _ in 0..count {
+        let def = &state.emitters[index];
+        let (lifetime, speed, angle, spread) = (def.lifetime, def.speed, def.angle, def.spread);
+        let angle = (angle + spread * (state.random() - 0.5)).to_radians();
+        let speed = state.random_range(speed);
+        let lifetime = state.random_range(lifetime);
+        state.particles.push(Particle {
+            emitter: index,
+            x,
+            y,
+            velocity: Vec2::new(angle.cos(), angle.sin()) * speed,
+            age: 0.0,
+            lifetime,
+        });
+    }
+}
+
+pub fn update_particles(state: &mut ParticlesState, dt: f32) {
+    state.dt = dt;
+    let emitters = &state.emitters;
+    state.particles.retain_mut(|particle| {
+        particle.age += dt;
+        particle.velocity.y += emitters[particle.emitter].gravity * dt;
+        particle.x += particle.velocity.x * dt;
+        particle.y += particle.velocity.y * dt;
+        particle.age < particle.lifetime
+    });
+}
+
+fn color_at(colors: &[Color], t: f32) -> Color {
+    if colors.len() == 1 {
+        return colors[0];
+    }
+    let pos = t.clamp(0.0, 1.0) * (colors.len() - 1) as f32;
+    let i = (pos.floor() as usize).min(colors.len() - 2);
+    let f = pos - i as f32;
+    let (a, b) = (colors[i], colors[i + 1]);
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * f) as u8;
+    Color::RGBA(lerp(a.r, b.r), lerp(a.g, b.g), lerp(a.b, b.b), lerp(a.a, b.a))
+}
+
+pub fn render_particles(render: &mut RenderingState, state: &ParticlesState) {
+    render.canvas.set_blend_mode(BlendMode::Blend);
+    for particle in state.particles.iter() {
+        let def = &state.emitters[particle.emitter];
+        let t = particle.age / particle.lifetime;
+        let color = color_at(&def.colors, t);
+        let x = particle.x as i32 + render.camera.x;
+        let y = particle.y as i32 + render.camera.y;
+        match &def.texture {
+            Some(path) if !def.frames.is_empty() => {
+                let handle = match render.assets.acquire_cached(&mut render.particle_textures, path) {
+                    Some(handle) => handle,
+                    None => continue,
+                };
+                let frame = &def.frames[((t * def.frames.len() as f32) as usize).min(def.frames.len() - 1)];
+                let src = sdl2::rect::Rect::new(frame.x, frame.y, frame.w, frame.h);
+                let dst = sdl2::rect::Rect::new(
+                    x - frame.w as i32 / 2,
+                    y - frame.h as i32 / 2,
+                    frame.w,
+                    frame.h,
+                );
+                let txt = match render.assets.texture(&render.texture_creator, handle) {
+                    Some(txt) => txt,
+                    None => continue,
+                };
+                txt.set_color_mod(color.r, color.g, color.b);
+                txt.set_alpha_mod(color.a);
+                render.canvas.copy(txt, src, dst).unwrap();
+            }
+            _ => {
+                let half = def.size as i32 / 2;
+                render.canvas.set_draw_color(color);
+                render
+                    .canvas
+                    .fill_rect(sdl2::rect::Rect::new(x - half, y - half, def.size, def.size))
+                    .unwrap();
+            }
+        }
+    }
+    render.canvas.set_blend_mode(BlendMode::None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_are_interpolated_over_the_lifetime() {
+        let red = Color::RGBA(255, 0, 0, 255);
+        let blue = Color::RGBA(0, 0, 255, 0);
+        assert_eq!(color_at(&[red], 0.7), red);
+        assert_eq!(color_at(&[red, blue], 0.0), red);
+        assert_eq!(color_at(&[red, blue], 0.5), Color::RGBA(127, 0, 127, 127));
+        assert_eq!(color_at(&[red, blue], 1.0), blue);
+        // particles can live a frame past their lifetime
+        assert_eq!(color_at(&[red, blue], 1.3), blue);
+        assert_eq!(color_at(&[red, Color::WHITE, blue], 0.5), Color::WHITE);
+        assert_eq!(color_at(&[red, Color::WHITE, blue], 0.25), Color::RGBA(255, 127, 127, 255));
+    }
+
+    #[test]
+    fn emitters_are_parsed() {
+        let burst = parse_emitter(&json::object! {
+            "mode": "burst",
+            "count": 6,
+            "lifetime": [0.2, 0.4],
+            "speed": 30,
+            "colors": [[255, 200, 100], [80, 80, 80, 0]],
+            "texture": "res/dust.png",
+            "frames": [[0, 0, 8, 8], [8, 0, 8, 8]],
+        })
+        .unwrap();
+        assert_eq!(burst.mode, EmitterMode::Burst(6));
+        assert_eq!(burst.lifetime, (0.2, 0.4));
+        assert_eq!(burst.speed, (30.0, 30.0));
+        assert_eq!(burst.colors, vec![Color::RGBA(255, 200, 100, 255), Color::RGBA(80, 80, 80, 0)]);
+        assert_eq!(burst.texture.as_deref(), Some("res/dust.png"));
+        assert_eq!(burst.frames.len(), 2);
+        assert_eq!(burst.frames[1].x, 8);
+
+        let continuous = parse_emitter(&json::object! {"mode": "continuous", "rate": 12.5}).unwrap();
+        assert_eq!(continuous.mode, EmitterMode::Continuous(12.5));
+        assert_eq!(continuous.lifetime, (1.0, 1.0));
+        assert_eq!(continuous.spread, 360.0);
+        assert_eq!(continuous.colors, vec![Color::WHITE]);
+        assert!(continuous.texture.is_none());
+    }
+
+    #[test]
+    fn emitters_without_a_valid_mode_are_skipped() {
+        assert!(parse_emitter(&json::object! {"mode": "fountain", "count": 3}).is_none());
+        assert!(parse_emitter(&json::object! {"count": 3}).is_none());
+    }
+}
@@ -1,8 +1,18 @@
-use tiled::PropertyValue;
-
-use crate::{enemy::EnemiesState, player::PlayerStateMachine, render::TilemapState, PlayerState};
-
-#[derive(Default, Clone)]
+use glam::Vec2;
+use project_tomato::properties::{DOOR, INSPECT_ID, MAP_CHANGE, SPAWN_PLACE, TRANSITION, TRANSITION_TIME};
+
+use crate::{
+    audio::{play_sfx, play_sfx_at, AudioState, Sfx},
+    enemy::EnemiesState,
+    map_error::{float_property, int_property, required, string_property, MapLoadError, MapLoadErrorKind},
+    particles::{emit_particles, ParticlesState},
+    player::PlayerStateMachine,
+    render::TilemapState,
+    transition::{TransitionKind, DEFAULT_TRANSITION_TIME},
+    PlayerState,
+};
+
+#[derive(Default, Clone, PartialEq)]
 pub struct Collider {
     pub x: i32,
     pub y: i32,
@@ -12,8 +22,8 @@ pub struct Collider {
 
 #[derive(Clone)]
 pub enum Interactions {
-    /// Map path and spawn number
-    ChangeMap(String, u32),
+    /// Map path, spawn number, transition and its duration
+    ChangeMap(String, u32, TransitionKind, f32),
     Inspect(u32),
 }
 
@@ -24,32 +34,49 @@ pub struct Interactable {
     pub is_in_collider: bool,
 }
 
+/// Collider that can be removed by the `open door` dialogue action
+#[derive(Clone)]
+pub struct Door {
+    pub name: String,
+    /// Index in `PhysicsState::colliders`
+    pub collider: usize,
+}
+
 #[derive(Clone)]
 pub struct PhysicsState {
     pub dt: f32,
+    pub time_scale: f32,
     pub dt_timer: std::time::Instant,
     pub colliders: Vec<Collider>,
     pub interactables: Vec<Interactable>,
+    /// Closed doors, their colliders are in `colliders` too
+    pub doors: Vec<Door>,
 }
 
 impl Default for PhysicsState {
     fn default() -> Self {
         Self {
             dt: 0.0,
+            time_scale: 1.0,
             dt_timer: std::time::Instant::now(),
             colliders: vec![],
             interactables: vec![],
+            doors: vec![],
         }
     }
 }
 
-pub fn load_tilemap_to_interactables(state: &mut PhysicsState, tile_state: &TilemapState) {
+pub fn load_tilemap_to_interactables(
+    state: &mut PhysicsState,
+    tile_state: &TilemapState,
+) -> Result<(), MapLoadError> {
     let mut interactables: Vec<Interactable> = vec![];
     for layer in tile_state.layers() {
         if layer.name == "Interactables" {
             match layer.layer_type() {
                 tiled::LayerType::ObjectLayer(obj_layer) => {
                     for obj in obj_layer.objects() {
+                        let at_object = |e: MapLoadError| e.in_object(&layer.name, obj.id());
                         match obj.shape {
                             tiled::ObjectShape::Rect { width, height } => {
                                 let x = obj.x as i32;
@@ -58,40 +85,47 @@ pub fn load_tilemap_to_interactables(state: &mut PhysicsState, tile_state: &Tile
                                 let h = height as u32;
                                 let col = Collider { x, y, w, h };
 
-                                if let Some(map_change) = obj.properties.get("map change") {
-                                    let map_path =
-                                        if let PropertyValue::StringValue(path) = map_change {
-                                            path
-                                        } else {
-                                            panic!()
+                                if let Some(map_path) =
+                                    string_property(&obj.properties, MAP_CHANGE).map_err(at_object)?
+                                {
+                                    let spawn_place =
+                                        required(int_property(&obj.properties, SPAWN_PLACE), SPAWN_PLACE)
+                                            .map_err(at_object)?;
+
+                                    let transition =
+                                        match string_property(&obj.properties, TRANSITION).map_err(at_object)? {
+                                            Some(name) => TransitionKind::from_name(name).ok_or_else(|| {
+                                                at_object(
+                                                    MapLoadError::new(MapLoadErrorKind::InvalidValue(format!(
+                                                        "unknown transition {}",
+                                                        name
+                                                    )))
+                                                    .with_property(TRANSITION.name),
+                                                )
+                                            })?,
+                                            None => TransitionKind::Fade,
                                         };
-                                    let spawn_place = if let PropertyValue::IntValue(spawn_place) =
-                                        obj.properties.get("spawn place").unwrap()
-                                    {
-                                        spawn_place
-                                    } else {
-                                        panic!()
-                                    };
+                                    let transition_time = float_property(&obj.properties, TRANSITION_TIME)
+                                        .map_err(at_object)?
+                                        .unwrap_or(DEFAULT_TRANSITION_TIME);
 
                                     let interaction = Interactions::ChangeMap(
                                         map_path.clone(),
-                                        *spawn_place as u32,
+                                        spawn_place as u32,
+                                        transition,
+                                        transition_time,
                                     );
                                     interactables.push(Interactable {
                                         collider: col,
                                         interaction: interaction,
                                         is_in_collider: false,
                                     })
-                                } else if let Some(inspect_id) = obj.properties.get("inspect id") {
-                                    let inspect_id =
-                                        if let PropertyValue::IntValue(inspect_id) = inspect_id {
-                                            inspect_id
-                                        } else {
-                                            panic!()
-                                        };
+                                } else if let Some(inspect_id) =
+                                    int_property(&obj.properties, INSPECT_ID).map_err(at_object)?
+                                {
                                     interactables.push(Interactable {
                                         collider: col,
-                                        interaction: Interactions::Inspect(*inspect_id as u32),
+                                        interaction: Interactions::Inspect(inspect_id as u32),
                                         is_in_collider: false,
                                     })
                                 }
@@ -106,9 +140,11 @@ pub fn load_tilemap_to_interactables(state: &mut PhysicsState, tile_state: &Tile
     }
 
     state.interactables.append(&mut interactables);
+    Ok(())
 }
 
-pub fn load_tilemap_to_physics(state: &mut PhysicsState, tile_state: &TilemapState) {
+/// Colliders with a `door` property are also registered as doors
+pub fn load_tilemap_to_physics(state: &mut PhysicsState, tile_state: &TilemapState) -> Result<(), MapLoadError> {
     let mut colliders: Vec<Collider> = vec![];
     for layer in tile_state.layers() {
         if layer.name == "Colliders" {
@@ -122,6 +158,12 @@ pub fn load_tilemap_to_physics(state: &mut PhysicsState, tile_state: &TilemapSta
                                 let w = width as u32;
                                 let h = height as u32;
                                 let col = Collider { x, y, w, h };
+                                if let Some(door) = string_property(&obj.properties, DOOR)
+                                    .map_err(|e| e.in_object(&layer.name, obj.id()))?
+                                {
+                                    let collider = state.colliders.len() + colliders.len();
+                                    state.doors.push(Door { name: door.clone(), collider });
+                                }
                                 colliders.push(col);
                             }
                             _ => {}
@@ -133,6 +175,7 @@ pub fn load_tilemap_to_physics(state: &mut PhysicsState, tile_state: &TilemapSta
         }
     }
     state.colliders.append(&mut colliders);
+    Ok(())
 }
 
 pub fn player_collision_interactables(physics: &mut PhysicsState, player: &mut PlayerState) {
@@ -161,7 +204,7 @@ fn is_colliding(x1: i32, y1: i32, w1: i32, h1: i32, x2: i32, y2: i32, w2: i32, h
     return x1 < x2 + w2 && x1 + w1 > x2 && y1 + h1 > y2 && y1 < y2 + h2;
 }
 
-pub fn enemies_physics(physics: &PhysicsState, enemies: &mut EnemiesState) {
+pub fn enemies_physics(physics: &PhysicsState, enemies: &mut EnemiesState, audio: &mut AudioState) {
     for enemy in enemies.enemies.iter_mut() {
         let obj = enemy;
         let max_speed: f32 = 50.0;
@@ -200,6 +243,13 @@ pub fn enemies_physics(physics: &PhysicsState, enemies: &mut EnemiesState) {
 
         if !is_colliding_x {
             obj.x = nx;
+        } else {
+            play_sfx_at(
+                audio,
+                Sfx::Bump,
+                obj.x + obj.width as f32 / 2.0,
+                obj.y + obj.height as f32 / 2.0,
+            );
         }
         if !is_colliding_y {
             obj.y = ny;
@@ -216,11 +266,18 @@ pub fn enemies_physics(physics: &PhysicsState, enemies: &mut EnemiesState) {
 
 pub fn count_dt(state: &mut PhysicsState) {
     let now = std::time::Instant::now();
-    state.dt = (now.duration_since(state.dt_timer).as_secs_f64()) as f32;
+    state.dt = (now.duration_since(state.dt_timer).as_secs_f64()) as f32 * state.time_scale;
     state.dt_timer = std::time::Instant::now();
 }
 
-pub fn player_enemies_hit(player: &mut PlayerState, enemies: &mut EnemiesState) {
+/// Stomps enemies the player falls on, returns how many were stomped
+pub fn player_enemies_hit(
+    player: &mut PlayerState,
+    enemies: &mut EnemiesState,
+    particles: &mut ParticlesState,
+    audio: &mut AudioState,
+) -> usize {
+    let count = enemies.enemies.len();
     enemies.enemies.retain_mut(|enemy| {
         if is_colliding(
             player.x as i32,
@@ -236,20 +293,50 @@ pub fn player_enemies_hit(player: &mut PlayerState, enemies: &mut EnemiesState)
                 // bounce the player
                 player.is_grounded = true;
                 player.jump_buffer_counter = 1.0;
+                emit_particles(
+                    particles,
+                    "juice",
+                    enemy.x + enemy.width as f32 / 2.0,
+                    enemy.y + enemy.height as f32 / 2.0,
+                );
+                play_sfx_at(
+                    audio,
+                    Sfx::Stomp,
+                    enemy.x + enemy.width as f32 / 2.0,
+                    enemy.y + enemy.height as f32 / 2.0,
+                );
                 // delete enemy
                 return false;
             } else {
-                player.state = PlayerStateMachine::Dying;
+                if !player.god_mode {
+                    player.state = PlayerStateMachine::Dying;
+                }
                 return true;
             }
         }
         return true;
     });
+    count - enemies.enemies.len()
 }
 
-pub fn player_physics(state: &PhysicsState, player: &mut PlayerState) {
+pub fn player_physics(
+    state: &PhysicsState,
+    player: &mut PlayerState,
+    particles: &mut ParticlesState,
+    audio: &mut AudioState,
+) {
     let mut obj = player;
     let dt = state.dt;
+    let was_falling = obj.state == PlayerStateMachine::Falling;
+
+    if obj.noclip {
+        let noclip_speed = 200.0;
+        obj.x += obj.wants_dir * noclip_speed * dt;
+        obj.y += obj.wants_vertical * noclip_speed * dt;
+        obj.velocity = Vec2::ZERO;
+        obj.added_velocity = Vec2::ZERO;
+        return;
+    }
 
     let max_speed: f32 = 150.0;
     let sliding_speed: f32 = 100.0;
@@ -290,12 +377,14 @@ pub fn player_physics(state: &PhysicsState, player: &mut PlayerState) {
         obj.wants_to_jump = false;
         obj.coyote_time_counter = 0.0;
         obj.jump_buffer_counter = 0.0;
+        play_sfx(audio, Sfx::Jump);
     }
     // wall jumping
     if obj.jump_buffer_counter > 0.0 && obj.is_sliding {
         obj.added_velocity.x = -obj.wants_dir * wall_jump_force;
         obj.velocity.y = -jump_force;
         obj.wants_to_jump = false;
+        play_sfx(audio, Sfx::Jump);
     }
 
     if obj.is_sliding {
@@ -369,6 +458,21 @@ pub fn player_physics(state: &PhysicsState, player: &mut PlayerState) {
         obj.y = 0.0;
     }
 
+    // particles
+    if was_falling && obj.is_grounded {
+        emit_particles(
+            particles,
+            "dust",
+            obj.x + obj.width as f32 / 2.0,
+            obj.y + obj.height as f32,
+        );
+        play_sfx(audio, Sfx::Land);
+    }
+    if obj.is_sliding && obj.velocity.y > 0.0 {
+        let side = if obj.wants_dir > 0.0 { obj.width as f32 } else { 0.0 };
+        emit_particles(particles, "wall dust", obj.x + side, obj.y + obj.height as f32);
+    }
+
     // state
     if is_falling
         && (obj.state == PlayerStateMachine::Walking || obj.state == PlayerStateMachine::Idling)
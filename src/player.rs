@@ -1,11 +1,11 @@
 use glam::Vec2;
-use r_i18n::I18n;
+use project_tomato::properties::SPAWN_PLACE;
 
 use crate::{
-    enemy::EnemiesState,
+    map_error::{MapLoadError, MapLoadErrorKind},
     physics::{Collider, Interactions, PhysicsState},
-    render::{RenderingState, TilemapState},
-    switch_map,
+    render::TilemapState,
+    transition::TransitionKind,
 };
 
 #[derive(Clone, PartialEq, Debug)]
@@ -32,6 +32,8 @@ pub struct PlayerState {
     pub acceleration: Vec2,
 
     pub wants_dir: f32,
+    /// Only used by noclip
+    pub wants_vertical: f32,
     pub wants_to_jump: bool,
     pub wants_to_interact: bool,
 
@@ -43,6 +45,9 @@ pub struct PlayerState {
     pub can_interact: bool,
     pub spawn_point: u32,
     pub current_map: String,
+
+    pub noclip: bool,
+    pub god_mode: bool,
 }
 
 impl PlayerState {
@@ -60,6 +65,7 @@ impl PlayerState {
                 h: 16,
             },
             wants_dir: 0.0,
+            wants_vertical: 0.0,
             added_velocity: Vec2::ZERO,
             velocity: Vec2::ZERO,
             acceleration: Vec2::ZERO,
@@ -73,24 +79,37 @@ impl PlayerState {
             state: PlayerStateMachine::Idling,
             spawn_point: 0,
             current_map: "".to_owned(),
+            noclip: false,
+            god_mode: false,
         }
     }
 }
 
-pub fn load_player_spawn(player: &mut PlayerState, tile: &TilemapState, spawn: u32) {
+/// Clears everything the player wants to do, used while the game ignores gameplay input
+pub fn lock_player_input(player: &mut PlayerState) {
+    player.wants_dir = 0.0;
+    player.wants_vertical = 0.0;
+    player.wants_to_jump = false;
+    player.wants_to_interact = false;
+    if player.state == PlayerStateMachine::Walking {
+        player.state = PlayerStateMachine::Idling;
+    }
+}
+
+pub fn load_player_spawn(player: &mut PlayerState, tile: &TilemapState, spawn: u32) -> Result<(), MapLoadError> {
     for layer in tile.layers() {
         if layer.name == "PlayerSpawners" {
             match layer.layer_type() {
                 tiled::LayerType::TileLayer(_) => {}
                 tiled::LayerType::ObjectLayer(obj) => {
                     for o in obj.objects() {
-                        if let Some(spawn_place_enum) = o.properties.get("spawn place") {
+                        if let Some(spawn_place_enum) = o.properties.get(SPAWN_PLACE.name) {
                             match spawn_place_enum {
                                 tiled::PropertyValue::IntValue(x) => {
                                     if *x == spawn as i32 {
                                         player.x = o.x;
                                         player.y = o.y - player.height as f32; // obj origin is bottom left in tiled whereas top left in sdl
-                                        return;
+                                        return Ok(());
                                     }
                                 }
                                 _ => {}
@@ -103,23 +122,17 @@ pub fn load_player_spawn(player: &mut PlayerState, tile: &TilemapState, spawn: u
             }
         }
     }
-    panic!("PLAYER SPAWN NOT FOUND");
+    Err(MapLoadError::new(MapLoadErrorKind::SpawnNotFound(spawn)).in_layer("PlayerSpawners"))
 }
 
 pub enum InteractionResult {
     Nothing,
-    ChangeMap(TilemapState),
+    /// Map path, spawn number, transition and its duration
+    ChangeMap(String, u32, TransitionKind, f32),
     Inspect(u32),
 }
 
-pub fn player_interact(
-    loader: &mut tiled::Loader,
-    lang: &I18n,
-    render: &mut RenderingState,
-    player: &mut PlayerState,
-    enemies: &mut EnemiesState,
-    physics: &mut PhysicsState,
-) -> InteractionResult {
+pub fn player_interact(player: &mut PlayerState, physics: &mut PhysicsState) -> InteractionResult {
     if player.can_interact
         && player.wants_to_interact
         && (player.state == PlayerStateMachine::Idling
@@ -136,10 +149,8 @@ pub fn player_interact(
             return InteractionResult::Nothing;
         }
         match interactable.unwrap().interaction {
-            Interactions::ChangeMap(path, numb) => {
-                return InteractionResult::ChangeMap(switch_map(
-                    loader, &path, numb, lang, render, player, enemies, physics,
-                ));
+            Interactions::ChangeMap(path, numb, transition, time) => {
+                return InteractionResult::ChangeMap(path, numb, transition, time);
             }
             Interactions::Inspect(inspect_id) => {
                 player.state = PlayerStateMachine::Talking;
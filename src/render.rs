@@ -1,23 +1,51 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use fontdue::layout::Layout;
 use fontdue::layout::LayoutSettings;
-use fontdue::layout::TextStyle;
-use fontdue::Font;
 use fontdue_sdl2::FontTexture;
-use r_i18n::I18n;
-use sdl2::image::LoadTexture;
+use project_tomato::properties::{FONT, TEXT};
 use sdl2::pixels::Color;
-use sdl2::rect::Rect;
 use sdl2::render::Canvas;
-use sdl2::render::Texture;
 use sdl2::render::TextureCreator;
 use sdl2::video::Window;
 use sdl2::video::WindowContext;
 use tiled::ObjectShape;
-use tiled::PropertyValue;
 
-use crate::DialogState;
+use crate::assets::AssetManager;
+use crate::assets::TextureHandle;
+use crate::bindings::Bindings;
+use crate::bindings::InputAction;
+use crate::console::render_console;
+use crate::console::ConsoleState;
+use crate::debug::render_debug;
+use crate::dialog::render_dialog;
+use crate::dialog::DialogState;
+use crate::flags::FlagStore;
+use crate::fonts::FontRegistry;
+use crate::gamepad::InputDevice;
+use crate::gamepad::BUTTON_GLYPHS;
+use crate::hud::render_hud;
+use crate::hud::HudState;
+use crate::i18n::Translator;
+use crate::debug::DebugState;
+use crate::lighting::render_lighting;
+use crate::lighting::LightingState;
+use crate::map_error::font_property;
+use crate::map_error::render_error_screen;
+use crate::map_error::required;
+use crate::map_error::string_property;
+use crate::map_error::ErrorScreenState;
+use crate::map_error::MapLoadError;
+use crate::map_error::MapLoadErrorKind;
+use crate::menu::render_menu;
+use crate::menu::MenuState;
+use crate::particles::render_particles;
+use crate::particles::ParticlesState;
+use crate::settings::renderer_vsync;
+use crate::settings::Settings;
+use crate::transition::render_transition;
+use crate::transition::TransitionState;
 use crate::EnemiesState;
 use crate::PhysicsState;
 use crate::PlayerState;
@@ -28,11 +56,33 @@ pub struct RenderingState {
     pub canvas: Canvas<Window>,
     pub camera: sdl2::rect::Rect,
     pub texture_creator: TextureCreator<WindowContext>,
-    pub textures: HashMap<String, Texture>,
+    pub assets: AssetManager,
+    /// Textures used by the current map, released on map switch
+    pub map_textures: Vec<TextureHandle>,
+    /// Tileset image path to texture
+    pub tileset_textures: HashMap<PathBuf, TextureHandle>,
+    pub arrow_glyph: Option<TextureHandle>,
+    /// Shown instead of the arrow glyph when playing with a gamepad
+    pub button_glyphs: HashMap<sdl2::controller::Button, TextureHandle>,
+    /// Particle sprites, acquired on first use and kept for the whole game
+    pub particle_textures: HashMap<String, TextureHandle>,
     pub font_texture: FontTexture,
-    pub fonts: Vec<Font>,
+    pub fonts: FontRegistry,
+    /// Whether presenting waits for vsync, see `renderer_vsync`
+    pub vsync: bool,
 
-    pub text_hints: Vec<Layout<Color>>,
+    pub text_hints: Vec<TextHint>,
+}
+
+/// Text placed in the `TextHints` layer, kept with its key so it can be translated again
+pub struct TextHint {
+    pub key: String,
+    pub font: usize,
+    /// Center of the top edge
+    pub x: f32,
+    pub y: f32,
+    pub size: f32,
+    pub layout: Layout<Color>,
 }
 #[derive(Clone)]
 pub struct AnimationFrame{
@@ -91,38 +141,95 @@ impl RenderingState {
     pub fn new(canvas: Canvas<Window>) -> RenderingState {
         let texture_creator = canvas.texture_creator();
         let font_texture = FontTexture::new(&texture_creator).unwrap();
-        let font = include_bytes!("../res/kongtext.ttf") as &[u8];
-        let kongtext = Font::from_bytes(font, fontdue::FontSettings::default()).unwrap();
-        let fonts = vec![kongtext];
+        let fonts = FontRegistry::load();
+        let vsync = renderer_vsync(&canvas);
+
+        let mut assets = AssetManager::new();
+        let arrow_glyph = assets
+            .acquire_texture("res/arrowglyph.png")
+            .map_err(|e| println!("{}", e))
+            .ok();
+        let mut button_glyphs = HashMap::new();
+        for (button, path) in BUTTON_GLYPHS {
+            match assets.acquire_texture(path) {
+                Ok(handle) => {
+                    button_glyphs.insert(*button, handle);
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
 
-        let mut x = RenderingState {
+        RenderingState {
             canvas,
             camera: sdl2::rect::Rect::new(0, 0, 0, 0),
             texture_creator, // camera width = map width etc
-            textures: HashMap::new(),
+            assets,
+            map_textures: vec![],
+            tileset_textures: HashMap::new(),
+            arrow_glyph,
+            button_glyphs,
+            particle_textures: HashMap::new(),
             font_texture: font_texture,
             fonts: fonts,
+            vsync,
 
             text_hints: vec![],
-        };
+        }
+    }
+}
 
-        let up_arrow_texture = x
-            .texture_creator
-            .load_texture("res/arrowglyph.png")
-            .unwrap();
-        x.textures
-            .insert("res/arrowglyph.png".to_owned(), up_arrow_texture);
-        x
+/// Glyph of the first button bound to `action` when playing with a gamepad, the arrow otherwise
+pub fn prompt_glyph(
+    state: &RenderingState,
+    bindings: &Bindings,
+    device: InputDevice,
+    action: InputAction,
+) -> Option<TextureHandle> {
+    let button = match device {
+        InputDevice::Gamepad => bindings.buttons(action).first(),
+        InputDevice::Keyboard => None,
+    };
+    button
+        .and_then(|button| state.button_glyphs.get(button).copied())
+        .or(state.arrow_glyph)
+}
+
+/// Acquires a texture that lives until the next map switch
+pub fn acquire_map_texture(state: &mut RenderingState, path: &str) -> Option<TextureHandle> {
+    match state.assets.acquire_texture(path) {
+        Ok(handle) => {
+            state.map_textures.push(handle);
+            Some(handle)
+        }
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
     }
 }
 
+/// Releases textures of the previous map and frees the ones the new map does not use,
+/// call after the new map has acquired its textures
+pub fn release_map_textures(state: &mut RenderingState, previous: Vec<TextureHandle>) {
+    for handle in previous {
+        state.assets.release_texture(handle);
+    }
+    state.assets.unload_unused();
+}
+
 pub fn load_tilemap_to_textures(state: &mut RenderingState, tile_state: &TilemapState) {
-    for tileset in tile_state.tilesets().to_owned() {
-        let image = tileset.image.as_ref().unwrap();
-        let path = image.source.as_os_str().to_str().unwrap();
-        let txt = state.texture_creator.load_texture(path).unwrap();
-        let name = tileset.name.clone();
-        state.textures.insert(name, txt);
+    state.tileset_textures.clear();
+    for tileset in tile_state.tilesets().iter() {
+        let image = if let Some(image) = tileset.image.as_ref() {
+            image
+        } else {
+            println!("Tileset {} has no image", tileset.name);
+            continue;
+        };
+        let path = image.source.to_string_lossy().into_owned();
+        if let Some(handle) = acquire_map_texture(state, &path) {
+            state.tileset_textures.insert(image.source.clone(), handle);
+        }
     }
     state
         .camera
@@ -132,88 +239,45 @@ pub fn load_tilemap_to_textures(state: &mut RenderingState, tile_state: &Tilemap
         .set_height(tile_state.height * tile_state.tile_height);
 }
 
-pub fn load_tilemap_to_text_hints(state: &mut RenderingState, tile: &TilemapState, lang: &I18n) {
+pub fn load_tilemap_to_text_hints(
+    state: &mut RenderingState,
+    tile: &TilemapState,
+    lang: &Translator,
+) -> Result<(), MapLoadError> {
     for layer in tile.layers() {
         if layer.name == "TextHints" {
             match layer.layer_type() {
                 tiled::LayerType::ObjectLayer(objl) => {
                     for obj in objl.objects() {
-                        let font = if let PropertyValue::IntValue(font) =
-                            obj.properties.get("font").unwrap()
-                        {
-                            font
-                        } else {
-                            panic!()
-                        };
-                        let text_untraslated = if let PropertyValue::StringValue(text) =
-                            obj.properties.get("text").unwrap()
-                        {
-                            text
-                        } else {
-                            panic!()
-                        };
-                        let text = lang.t(&text_untraslated).as_str().unwrap();
-                        let height = if let ObjectShape::Rect { height, .. } = obj.shape {
+                        let at_object = |e: MapLoadError| e.in_object(&layer.name, obj.id());
+                        let font = required(font_property(&obj.properties, FONT, &state.fonts), FONT)
+                            .map_err(at_object)?;
+                        let key = required(string_property(&obj.properties, TEXT), TEXT)
+                            .map_err(at_object)?;
+                        let size = if let ObjectShape::Rect { height, .. } = obj.shape {
                             height
                         } else {
-                            panic!()
+                            return Err(at_object(MapLoadError::new(
+                                MapLoadErrorKind::InvalidValue("text hint is not a rectangle".to_owned()),
+                            )));
                         };
-                        let mut layout =
-                            Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown);
-                        let mut width = 0.0;
-                        for chr in text.to_owned().chars() {
-                            width += state
-                                .fonts
-                                .get(*font as usize)
-                                .unwrap()
-                                .metrics(chr, height)
-                                .advance_width;
-                        }
-                        layout.reset(&LayoutSettings {
-                            x: obj.x - width / 2.0,
+                        let mut hint = TextHint {
+                            key: key.clone(),
+                            font,
+                            x: obj.x,
                             y: obj.y,
-                            ..Default::default()
-                        });
-                        layout.append(
-                            state.fonts.as_slice(),
-                            &TextStyle::with_user_data(text, height, *font as usize, Color::WHITE),
-                        );
-                        state.text_hints.push(layout);
+                            size,
+                            layout: Layout::new(fontdue::layout::CoordinateSystem::PositiveYDown),
+                        };
+                        layout_text_hint(&state.fonts, &mut hint, lang);
+                        state.text_hints.push(hint);
                     }
                 }
                 _ => {}
             }
         }
     }
-}
-
-fn render_dialog(render: &mut RenderingState, dialog: &mut DialogState, _lang: &I18n){
-    if !dialog.show {return}
-    let (canvas_w, canvas_h) = render.canvas.logical_size();
-
-    let margin = 5u32;
-    //let frame = 2u32;
-    let height = 70u32;
-    let bg = Rect::new((margin) as i32, (canvas_h - margin - height) as i32,canvas_w - margin - margin, height);
-    render.canvas.set_draw_color(Color::BLACK);
-    render.canvas.fill_rect(bg).unwrap();
-    render.canvas.set_draw_color(dialog.color);
-    render.canvas.draw_rect(bg).unwrap();
-    let settings = LayoutSettings{ x: (margin + margin) as f32, y: (canvas_h-margin+margin-height) as f32, max_width: Some((canvas_w-4*margin) as f32), ..LayoutSettings::default() };
-    dialog.layout.reset(&settings);
-    let font_size = 8.0;
-    dialog.current_char+=1;
-    if dialog.current_char > dialog.text.len(){
-        dialog.finished = true;
-        dialog.current_char = dialog.text.len();
-    }
-    let text = &dialog.text[..dialog.current_char];
-    dialog.layout.append(render.fonts.as_slice(), &TextStyle::with_user_data(text, font_size, dialog.font, Color::WHITE));
-    if dialog.layout.lines().unwrap().last().unwrap().baseline_y > (canvas_h - margin) as f32{ // SOME ERROR HANDLING PLZ
-        println!("PRZYPAŁ");
-    }
-
-    render.font_texture.draw_text(&mut render.canvas, &render.fonts, dialog.layout.glyphs()).unwrap();
+    Ok(())
 }
 
 fn render_tilemap(state: &mut RenderingState, tile_state: &TilemapState) {
@@ -228,8 +292,14 @@ fn render_tilemap(state: &mut RenderingState, tile_state: &TilemapState) {
                             for j in 0..width {
                                 if let Some(tile) = tiles.get_tile(j as i32, i as i32) {
                                     let tile_prop = tile.get_tile().unwrap(); // get another fucking version of tile data
-                                    let tilemap_id = tile_prop.tileset().name.clone(); // used for texture
-                                    let txt = state.textures.get(&tilemap_id).unwrap(); // get texture
+                                    let handle = match tile_prop.tileset().image.as_ref().and_then(|image| state.tileset_textures.get(&image.source)) {
+                                        Some(handle) => *handle,
+                                        None => continue,
+                                    };
+                                    let txt = match state.assets.texture(&state.texture_creator, handle) {
+                                        Some(txt) => txt,
+                                        None => continue,
+                                    }; // get texture
 
                                     let tile_width = tile_prop.tileset().tile_width; // width of tile in pixels
                                     let tile_height = tile_prop.tileset().tile_height;
@@ -268,13 +338,33 @@ fn render_tilemap(state: &mut RenderingState, tile_state: &TilemapState) {
     }
 }
 
+/// Lays out the hint in the current language, centered on its position
+fn layout_text_hint(fonts: &FontRegistry, hint: &mut TextHint, lang: &Translator) {
+    let translation = lang.t(&hint.key);
+    let text = translation.text.as_ref();
+    let width = fonts.text_width(text, hint.size, hint.font);
+    hint.layout.reset(&LayoutSettings {
+        x: hint.x - width / 2.0,
+        y: hint.y,
+        ..Default::default()
+    });
+    fonts.append(&mut hint.layout, text, hint.size, hint.font, translation.color(Color::WHITE));
+}
+
+/// Lays out text hints again after the language changed
+pub fn retranslate_text_hints(state: &mut RenderingState, lang: &Translator) {
+    for hint in state.text_hints.iter_mut() {
+        layout_text_hint(&state.fonts, hint, lang);
+    }
+}
+
 pub fn render_text_hints(state: &mut RenderingState) {
-    for layout in state.text_hints.iter() {
+    for layout in state.text_hints.iter().map(|hint| &hint.layout) {
         state
             .font_texture
             .draw_text_at(
                 &mut state.canvas,
-                &state.fonts,
+                state.fonts.as_slice(),
                 layout.glyphs(),
                 state.camera.x,
                 state.camera.y,
@@ -285,12 +375,24 @@ pub fn render_text_hints(state: &mut RenderingState) {
 
 pub fn render(
     state: &mut RenderingState,
-    lang: &mut I18n,
+    lang: &mut Translator,
     tile_state: &TilemapState,
     player: &PlayerState,
     enemies: &EnemiesState,
-    _physics: &PhysicsState,
+    physics: &PhysicsState,
+    particles: &ParticlesState,
+    lighting: &mut LightingState,
+    transition: &TransitionState,
+    menu: &mut MenuState,
+    settings: &Settings,
+    debug: &mut DebugState,
+    console: &mut ConsoleState,
     dialog: &mut DialogState,
+    error_screen: &mut ErrorScreenState,
+    hud: &mut HudState,
+    flags: &FlagStore,
+    bindings: &Bindings,
+    device: InputDevice,
 ) {
     state.canvas.set_draw_color(Color::RGB(0, 0, 0));
     state.canvas.clear();
@@ -325,6 +427,7 @@ pub fn render(
 
     render_tilemap(state, tile_state);
     render_text_hints(state);
+    render_particles(state, particles);
     render_enemies(state, enemies);
     
     // render player
@@ -337,6 +440,8 @@ pub fn render(
     state.canvas.set_draw_color(Color::RGB(255, 255, 0));
     state.canvas.fill_rect(dst).unwrap();
 
+    render_lighting(state, lighting);
+
     if player.can_interact {
         let dst = sdl2::rect::Rect::new(
             player.x as i32 + state.camera.x + (0.5 * player.width as f32) as i32 - 4,
@@ -344,13 +449,21 @@ pub fn render(
             8,
             8,
         );
-        let txt = state.textures.get_mut("res/arrowglyph.png").unwrap();
-        txt.set_color_mod(255, 255, 255);
-        state.canvas.copy(txt, None, dst).unwrap();
+        if let Some(txt) = prompt_glyph(state, bindings, device, InputAction::Interact)
+            .and_then(|handle| state.assets.texture(&state.texture_creator, handle))
+        {
+            txt.set_color_mod(255, 255, 255);
+            state.canvas.copy(txt, None, dst).unwrap();
+        }
     }
     
-    //_render_colliders(state,player,_physics);
-    render_dialog(state,dialog,lang);
+    render_hud(state, hud, flags, lang);
+    render_debug(state, debug, player, physics, enemies);
+    render_dialog(state,dialog,lang,bindings,device);
+    render_transition(state, transition, player);
+    render_menu(state, menu, settings, lang, bindings, device);
+    render_error_screen(state, error_screen, lang, bindings, device);
+    render_console(state, console);
     state.canvas.present();
 }
 
@@ -368,23 +481,8 @@ pub fn render_enemies(state: &mut RenderingState, enemies: &EnemiesState){
             enemy.width,
             enemy.height
         );
-        let txt = state.textures.get(&enemy.texture_path).unwrap();
-        state.canvas.copy(txt, src, dst).unwrap();
-    }
-}
-
-pub fn _render_colliders(state: &mut RenderingState, player: &PlayerState, physics: &PhysicsState) {
-    state.canvas.set_draw_color(Color::RGB(255, 0, 0));
-    for col in physics.colliders.iter() {
-        let rect = sdl2::rect::Rect::new(col.x, col.y, col.w, col.h);
-        state.canvas.draw_rect(rect).unwrap();
+        if let Some(txt) = state.assets.texture(&state.texture_creator, enemy.texture) {
+            state.canvas.copy(txt, src, dst).unwrap();
+        }
     }
-    let player_rect = sdl2::rect::Rect::new(
-        player.x as i32 + player.hitbox.x + state.camera.x,
-        player.y as i32 + player.hitbox.y + state.camera.y,
-        player.hitbox.w,
-        player.hitbox.h,
-    );
-    state.canvas.set_draw_color(Color::RGB(255, 0, 255));
-    state.canvas.draw_rect(player_rect).unwrap();
 }
@@ -0,0 +1,277 @@
+use json::JsonValue;
+use sdl2::render::Canvas;
+use sdl2::video::{FullscreenType, Window, WindowPos};
+
+pub const SETTINGS_PATH: &str = "settings.json";
+
+/// Logical resolution the game is designed for, (320, 180) for 16:9
+pub const BASE_WIDTH: u32 = 320;
+pub const BASE_HEIGHT: u32 = 180;
+
+pub const RESOLUTIONS: [(u32, u32); 8] = [
+    (640, 360),
+    (1024, 768),
+    (1280, 720),
+    (1280, 800),
+    (1600, 900),
+    (1920, 1080),
+    (2560, 1080),
+    (2560, 1440),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WindowMode {
+    Windowed,
+    Fullscreen,
+    Borderless,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AspectMode {
+    /// Keeps the 16:9 view and adds black bars
+    Letterbox,
+    /// Shows more of the map on 4:3, 16:10 and ultrawide screens
+    Expand,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct VideoSettings {
+    pub window_mode: WindowMode,
+    pub width: u32,
+    pub height: u32,
+    /// Pixel perfect scaling, may add black bars on every side
+    pub integer_scale: bool,
+    pub aspect_mode: AspectMode,
+    pub vsync: bool,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        Self {
+            window_mode: WindowMode::Borderless,
+            width: 1280,
+            height: 720,
+            integer_scale: false,
+            aspect_mode: AspectMode::Letterbox,
+            vsync: false,
+        }
+    }
+}
+
+/// Volumes from 0.0 to 1.0, music and sound effects are scaled by `master`
+#[derive(Clone, PartialEq, Debug)]
+pub struct AudioSettings {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            music: 0.7,
+            sfx: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Settings {
+    pub video: VideoSettings,
+    pub audio: AudioSettings,
+    /// Locale code, checked against `LOCALES` on startup
+    pub language: String,
+    /// Language from `--lang`, `TOMATO_LANG` or the console, used instead of `language` but never saved
+    pub language_override: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            video: VideoSettings::default(),
+            audio: AudioSettings::default(),
+            language: "en".to_owned(),
+            language_override: None,
+        }
+    }
+}
+
+impl WindowMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WindowMode::Windowed => "windowed",
+            WindowMode::Fullscreen => "fullscreen",
+            WindowMode::Borderless => "borderless",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "windowed" => Some(WindowMode::Windowed),
+            "fullscreen" => Some(WindowMode::Fullscreen),
+            "borderless" => Some(WindowMode::Borderless),
+            _ => None,
+        }
+    }
+}
+
+impl AspectMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AspectMode::Letterbox => "letterbox",
+            AspectMode::Expand => "expand",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "letterbox" => Some(AspectMode::Letterbox),
+            "expand" => Some(AspectMode::Expand),
+            _ => None,
+        }
+    }
+}
+
+impl Settings {
+    /// Language the game is shown in
+    pub fn current_language(&self) -> &str {
+        self.language_override.as_deref().unwrap_or(&self.language)
+    }
+
+    /// Loads settings from `settings.json`, missing or invalid values fall back to defaults
+    pub fn load() -> Self {
+        let mut settings = Settings::default();
+        let data = match std::fs::read_to_string(SETTINGS_PATH) {
+            Ok(data) => data,
+            Err(_) => return settings,
+        };
+        let data = match json::parse(&data) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Invalid {}: {}", SETTINGS_PATH, e);
+                return settings;
+            }
+        };
+        let video = &data["video"];
+        let defaults = VideoSettings::default();
+        settings.video = VideoSettings {
+            window_mode: video["window_mode"]
+                .as_str()
+                .and_then(WindowMode::from_name)
+                .unwrap_or(defaults.window_mode),
+            width: video["width"].as_u32().unwrap_or(defaults.width),
+            height: video["height"].as_u32().unwrap_or(defaults.height),
+            integer_scale: video["integer_scale"]
+                .as_bool()
+                .unwrap_or(defaults.integer_scale),
+            aspect_mode: video["aspect_mode"]
+                .as_str()
+                .and_then(AspectMode::from_name)
+                .unwrap_or(defaults.aspect_mode),
+            vsync: video["vsync"].as_bool().unwrap_or(defaults.vsync),
+        };
+        let audio = &data["audio"];
+        let defaults = AudioSettings::default();
+        let volume = |value: &JsonValue, default: f32| value.as_f32().unwrap_or(default).clamp(0.0, 1.0);
+        settings.audio = AudioSettings {
+            master: volume(&audio["master"], defaults.master),
+            music: volume(&audio["music"], defaults.music),
+            sfx: volume(&audio["sfx"], defaults.sfx),
+        };
+        if let Some(language) = data["language"].as_str() {
+            settings.language = language.to_owned();
+        }
+        settings
+    }
+
+    pub fn save(&self) {
+        let mut data = JsonValue::new_object();
+        data["video"] = json::object! {
+            window_mode: self.video.window_mode.name(),
+            width: self.video.width,
+            height: self.video.height,
+            integer_scale: self.video.integer_scale,
+            aspect_mode: self.video.aspect_mode.name(),
+            vsync: self.video.vsync,
+        };
+        data["audio"] = json::object! {
+            master: self.audio.master,
+            music: self.audio.music,
+            sfx: self.audio.sfx,
+        };
+        data["language"] = self.language.as_str().into();
+        if let Err(e) = std::fs::write(SETTINGS_PATH, data.pretty(4)) {
+            println!("Failed to save {}: {}", SETTINGS_PATH, e);
+        }
+    }
+}
+
+// sdl2 0.35 can only turn vsync on while building the canvas, so the SDL function that switches it
+// on an existing renderer is declared here. It was added in SDL 2.0.18 and fails to link with older
+// versions, so it is behind the opt-in `vsync-toggle` feature. Without it vsync changes apply on the
+// next start.
+#[cfg(feature = "vsync-toggle")]
+extern "C" {
+    fn SDL_RenderSetVSync(renderer: *mut sdl2::sys::SDL_Renderer, vsync: i32) -> i32;
+}
+
+#[cfg(feature = "vsync-toggle")]
+fn set_vsync(canvas: &mut Canvas<Window>, vsync: bool) {
+    if unsafe { SDL_RenderSetVSync(canvas.raw(), vsync as i32) } != 0 {
+        println!("Failed to set vsync: {}", sdl2::get_error());
+    }
+}
+
+/// Vsync is only set when the canvas is built
+#[cfg(not(feature = "vsync-toggle"))]
+fn set_vsync(_canvas: &mut Canvas<Window>, _vsync: bool) {}
+
+/// Whether presenting actually waits for vsync, the setting may only apply after a restart
+pub fn renderer_vsync(canvas: &Canvas<Window>) -> bool {
+    canvas.info().flags & sdl2::sys::SDL_RendererFlags::SDL_RENDERER_PRESENTVSYNC as u32 != 0
+}
+
+pub fn apply_video_settings(canvas: &mut Canvas<Window>, video: &VideoSettings) -> Result<(), String> {
+    let window = canvas.window_mut();
+    match video.window_mode {
+        WindowMode::Windowed => {
+            window.set_fullscreen(FullscreenType::Off)?;
+            window.set_bordered(true);
+            window
+                .set_size(video.width, video.height)
+                .map_err(|e| e.to_string())?;
+            window.set_position(WindowPos::Centered, WindowPos::Centered);
+        }
+        WindowMode::Fullscreen => {
+            window
+                .set_size(video.width, video.height)
+                .map_err(|e| e.to_string())?;
+            window.set_fullscreen(FullscreenType::True)?;
+        }
+        WindowMode::Borderless => {
+            window.set_fullscreen(FullscreenType::Desktop)?;
+        }
+    }
+    canvas.set_integer_scale(video.integer_scale)?;
+    set_vsync(canvas, video.vsync);
+    update_logical_size(canvas, video)
+}
+
+/// Recalculates the logical resolution for the current window size, call after resizing
+pub fn update_logical_size(canvas: &mut Canvas<Window>, video: &VideoSettings) -> Result<(), String> {
+    let (output_w, output_h) = canvas.output_size()?;
+    let (w, h) = match video.aspect_mode {
+        AspectMode::Letterbox => (BASE_WIDTH, BASE_HEIGHT),
+        AspectMode::Expand => {
+            if output_w * BASE_HEIGHT >= output_h * BASE_WIDTH {
+                // wider than 16:9, keep height
+                (BASE_HEIGHT * output_w / output_h.max(1), BASE_HEIGHT)
+            } else {
+                // narrower than 16:9 (4:3, 16:10), keep width
+                (BASE_WIDTH, BASE_WIDTH * output_h / output_w.max(1))
+            }
+        }
+    };
+    canvas.set_logical_size(w, h).map_err(|e| e.to_string())
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use fontdue::layout::{Layout, LayoutSettings};
+use json::JsonValue;
+use project_tomato::SPEAKERS_PATH;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::{
+    assets::TextureHandle,
+    render::{AnimationFrame, RenderingState},
+};
+
+/// Voice of speakers without their own and of text without a speaker
+pub const DEFAULT_VOICE: &str = "res/sfx/blip.wav";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PortraitSide {
+    Left,
+    Right,
+}
+
+#[derive(Clone)]
+pub struct Portrait {
+    pub texture: String,
+    pub idle: Vec<AnimationFrame>,
+    /// Played while the text is being typed
+    pub talking: Vec<AnimationFrame>,
+    pub frame_time: f32,
+}
+
+/// Blip played while the speaker's text is typed
+#[derive(Clone, PartialEq, Debug)]
+pub struct SpeakerVoice {
+    pub sound: String,
+    /// Revealed chars between blips, spaces are not counted
+    pub every: usize,
+    pub pitch: f32,
+    /// Random pitch change of every blip, either way
+    pub variation: f32,
+    pub volume: f32,
+}
+
+impl Default for SpeakerVoice {
+    fn default() -> Self {
+        Self {
+            sound: DEFAULT_VOICE.to_owned(),
+            every: 2,
+            pitch: 1.0,
+            variation: 0.08,
+            volume: 0.4,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Speaker {
+    /// Translation key of the name tag
+    pub name: String,
+    /// Dialog box border and name tag colour
+    pub color: Color,
+    pub side: PortraitSide,
+    pub portrait: Option<Portrait>,
+    pub voice: SpeakerVoice,
+}
+
+fn parse_frames(value: &JsonValue) -> Vec<AnimationFrame> {
+    value
+        .members()
+        .map(|f| AnimationFrame {
+            x: f[0].as_i32().unwrap_or(0),
+            y: f[1].as_i32().unwrap_or(0),
+            w: f[2].as_u32().unwrap_or(1),
+            h: f[3].as_u32().unwrap_or(1),
+        })
+        .collect()
+}
+
+fn parse_speaker(id: &str, value: &JsonValue) -> Speaker {
+    let color = &value["color"];
+    let portrait = &value["portrait"];
+    let voice = &value["voice"];
+    let default_voice = SpeakerVoice::default();
+    Speaker {
+        name: value["name"].as_str().unwrap_or(id).to_owned(),
+        color: if color.is_array() {
+            Color::RGB(
+                color[0].as_u8().unwrap_or(255),
+                color[1].as_u8().unwrap_or(255),
+                color[2].as_u8().unwrap_or(255),
+            )
+        } else {
+            Color::GREEN
+        },
+        side: match value["side"].as_str() {
+            Some("right") => PortraitSide::Right,
+            _ => PortraitSide::Left,
+        },
+        portrait: portrait["texture"].as_str().map(|texture| {
+            let idle = parse_frames(&portrait["idle"]);
+            let talking = parse_frames(&portrait["talking"]);
+            Portrait {
+                texture: texture.to_owned(),
+                talking: if talking.is_empty() { idle.clone() } else { talking },
+                idle,
+                frame_time: portrait["frame time"].as_f32().unwrap_or(0.15),
+            }
+        }),
+        voice: SpeakerVoice {
+            sound: voice["sound"].as_str().unwrap_or(DEFAULT_VOICE).to_owned(),
+            every: voice["every"].as_usize().unwrap_or(default_voice.every).max(1),
+            pitch: voice["pitch"].as_f32().unwrap_or(default_voice.pitch),
+            variation: voice["variation"].as_f32().unwrap_or(default_voice.variation),
+            volume: voice["volume"].as_f32().unwrap_or(default_voice.volume),
+        },
+    }
+}
+
+pub fn load_speakers() -> HashMap<String, Speaker> {
+    let data = match std::fs::read_to_string(SPEAKERS_PATH) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Failed to load {}: {}", SPEAKERS_PATH, e);
+            return HashMap::new();
+        }
+    };
+    let data = match json::parse(&data) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Failed to parse {}: {}", SPEAKERS_PATH, e);
+            return HashMap::new();
+        }
+    };
+    data.entries()
+        .map(|(id, value)| (id.to_owned(), parse_speaker(id, value)))
+        .collect()
+}
+
+/// Size of the first portrait frame, portraits without frames take no space
+pub fn portrait_size(speaker: &Speaker) -> Option<(u32, u32)> {
+    let portrait = speaker.portrait.as_ref()?;
+    let frame = portrait.idle.first().or(portrait.talking.first())?;
+    Some((frame.w, frame.h))
+}
+
+/// Draws the current portrait frame with its top left corner at `x`, `y`
+pub fn render_portrait(
+    render: &mut RenderingState,
+    speaker: &Speaker,
+    textures: &mut HashMap<String, TextureHandle>,
+    talking: bool,
+    time: f32,
+    x: i32,
+    y: i32,
+) {
+    let portrait = if let Some(portrait) = &speaker.portrait { portrait } else { return };
+    let frames = if talking { &portrait.talking } else { &portrait.idle };
+    if frames.is_empty() {
+        return;
+    }
+    let handle = if let Some(handle) = render.assets.acquire_cached(textures, &portrait.texture) {
+        handle
+    } else {
+        return;
+    };
+    let frame = &frames[(time / portrait.frame_time.max(0.01)) as usize % frames.len()];
+    if let Some(txt) = render.assets.texture(&render.texture_creator, handle) {
+        let src = Rect::new(frame.x, frame.y, frame.w, frame.h);
+        render.canvas.copy(txt, src, Rect::new(x, y, frame.w, frame.h)).unwrap();
+    }
+}
+
+/// Draws `name` in a small box sitting on top of the dialog box, aligned to `side`
+pub fn render_name_tag(
+    render: &mut RenderingState,
+    layout: &mut Layout<Color>,
+    name: &str,
+    color: Color,
+    side: PortraitSide,
+    box_rect: Rect,
+) {
+    let font_size = 8.0;
+    let padding = 3;
+    let width = render.fonts.text_width(name, font_size, 0);
+    let tag_w = width.ceil() as u32 + 2 * padding;
+    let tag_h = font_size as u32 + 2 * padding;
+    let tag_x = match side {
+        PortraitSide::Left => box_rect.x() + padding as i32,
+        PortraitSide::Right => box_rect.right() - padding as i32 - tag_w as i32,
+    };
+    let tag = Rect::new(tag_x, box_rect.y() - tag_h as i32 + 1, tag_w, tag_h);
+    render.canvas.set_draw_color(Color::BLACK);
+    render.canvas.fill_rect(tag).unwrap();
+    render.canvas.set_draw_color(color);
+    render.canvas.draw_rect(tag).unwrap();
+
+    layout.reset(&LayoutSettings {
+        x: (tag.x() + padding as i32) as f32,
+        y: (tag.y() + padding as i32) as f32,
+        ..LayoutSettings::default()
+    });
+    render.fonts.append(layout, name, font_size, 0, color);
+    render
+        .font_texture
+        .draw_text(&mut render.canvas, render.fonts.as_slice(), layout.glyphs())
+        .unwrap();
+}
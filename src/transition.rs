@@ -0,0 +1,169 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::BlendMode;
+
+use crate::{player::PlayerState, render::RenderingState};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TransitionKind {
+    None,
+    Fade,
+    Iris,
+    Slide,
+}
+
+impl TransitionKind {
+    /// Name as used in the `transition` Tiled property
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "fade" => Some(Self::Fade),
+            "iris" => Some(Self::Iris),
+            "slide" => Some(Self::Slide),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TransitionPhase {
+    Idle,
+    /// Covering the screen, map is switched when it ends
+    Out,
+    /// Uncovering the new map
+    In,
+}
+
+pub struct TransitionState {
+    pub kind: TransitionKind,
+    pub phase: TransitionPhase,
+    pub timer: f32,
+    /// Duration of a single phase in seconds
+    pub duration: f32,
+    /// Map path and spawn number to switch to
+    pub target: Option<(String, u32)>,
+}
+
+impl TransitionState {
+    pub fn new() -> Self {
+        Self {
+            kind: TransitionKind::None,
+            phase: TransitionPhase::Idle,
+            timer: 0.0,
+            duration: 0.0,
+            target: None,
+        }
+    }
+}
+
+pub const DEFAULT_TRANSITION_TIME: f32 = 0.4;
+
+pub fn start_transition(
+    state: &mut TransitionState,
+    kind: TransitionKind,
+    duration: f32,
+    path: String,
+    spawn_number: u32,
+) {
+    state.kind = kind;
+    state.phase = TransitionPhase::Out;
+    state.timer = 0.0;
+    state.duration = duration;
+    state.target = Some((path, spawn_number));
+}
+
+pub fn is_transitioning(state: &TransitionState) -> bool {
+    state.phase != TransitionPhase::Idle
+}
+
+/// Advances the transition, returns the map to switch to on the frame the screen gets fully covered
+pub fn update_transition(state: &mut TransitionState, dt: f32) -> Option<(String, u32)> {
+    match state.phase {
+        TransitionPhase::Idle => None,
+        TransitionPhase::Out => {
+            state.timer += dt;
+            if state.kind == TransitionKind::None || state.timer >= state.duration {
+                state.timer = 0.0;
+                state.phase = if state.kind == TransitionKind::None {
+                    TransitionPhase::Idle
+                } else {
+                    TransitionPhase::In
+                };
+                return state.target.take();
+            }
+            None
+        }
+        TransitionPhase::In => {
+            state.timer += dt;
+            if state.timer >= state.duration {
+                state.phase = TransitionPhase::Idle;
+            }
+            None
+        }
+    }
+}
+
+/// How much of the screen is covered, 0.0 - 1.0
+fn coverage(state: &TransitionState) -> f32 {
+    let t = if state.duration > 0.0 {
+        (state.timer / state.duration).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    match state.phase {
+        TransitionPhase::Idle => 0.0,
+        TransitionPhase::Out => t,
+        TransitionPhase::In => 1.0 - t,
+    }
+}
+
+pub fn render_transition(render: &mut RenderingState, state: &TransitionState, player: &PlayerState) {
+    if !is_transitioning(state) {
+        return;
+    }
+    let c = coverage(state);
+    let (canvas_w, canvas_h) = render.canvas.logical_size();
+    render.canvas.set_draw_color(Color::BLACK);
+    match state.kind {
+        TransitionKind::None => {}
+        TransitionKind::Fade => {
+            render.canvas.set_blend_mode(BlendMode::Blend);
+            render.canvas.set_draw_color(Color::RGBA(0, 0, 0, (c * 255.0) as u8));
+            render.canvas.fill_rect(None).unwrap();
+            render.canvas.set_blend_mode(BlendMode::None);
+        }
+        TransitionKind::Slide => {
+            let w = (canvas_w as f32 * c) as u32;
+            // slides in from the left and leaves to the right
+            let x = if state.phase == TransitionPhase::Out { 0 } else { (canvas_w - w) as i32 };
+            if w > 0 {
+                render.canvas.fill_rect(Rect::new(x, 0, w, canvas_h)).unwrap();
+            }
+        }
+        TransitionKind::Iris => {
+            // circle centered on the player, everything outside of it is black
+            let cx = player.x + player.width as f32 / 2.0 + render.camera.x as f32;
+            let cy = player.y + player.height as f32 / 2.0 + render.camera.y as f32;
+            let max_radius = ((canvas_w * canvas_w + canvas_h * canvas_h) as f32).sqrt();
+            let r = max_radius * (1.0 - c);
+            let mut rects = vec![];
+            for y in 0..canvas_h {
+                let dy = y as f32 - cy;
+                if dy.abs() >= r {
+                    rects.push(Rect::new(0, y as i32, canvas_w, 1));
+                    continue;
+                }
+                let dx = (r * r - dy * dy).sqrt();
+                let left = (cx - dx).max(0.0) as u32;
+                let right = (cx + dx).min(canvas_w as f32) as u32;
+                if left > 0 {
+                    rects.push(Rect::new(0, y as i32, left, 1));
+                }
+                if right < canvas_w {
+                    rects.push(Rect::new(right as i32, y as i32, canvas_w - right, 1));
+                }
+            }
+            render.canvas.fill_rects(&rects).unwrap();
+        }
+    }
+}